@@ -0,0 +1,53 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+fn bench<F: FnMut()>(name: &str, iters: u32, mut f: F) {
+    for _ in 0..iters / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..iters {
+        f();
+    }
+    let elapsed = start.elapsed();
+    println!("{:<32} {:>10.1} ns/iter", name, elapsed.as_nanos() as f64 / iters as f64);
+}
+
+fn main() {
+    bench("complete_then_take", 1_000_000, || {
+        let (notifier, mut notify_future) = notify_future::pair::<u64>();
+        notifier.set_complete(black_box(1)).unwrap();
+        black_box(notify_future.try_take().unwrap());
+    });
+
+    bench("complete_then_wait", 1_000_000, || {
+        let (notifier, notify_future) = notify_future::pair::<u64>();
+        notifier.set_complete(black_box(1)).unwrap();
+        black_box(notify_future.wait().unwrap());
+    });
+
+    bench("is_complete_poll", 1_000_000, || {
+        let (notifier, notify_future) = notify_future::pair::<u64>();
+        for _ in 0..16 {
+            black_box(notify_future.is_complete());
+        }
+        drop(notifier);
+    });
+
+    let pool: Vec<_> = (0..4).map(|_| {
+        let (tx, rx) = std::sync::mpsc::channel::<notify_future::Notifier<u64>>();
+        std::thread::spawn(move || {
+            while let Ok(notifier) = rx.recv() {
+                let _ = notifier.set_complete(1);
+            }
+        });
+        tx
+    }).collect();
+    let mut i = 0;
+    bench("cross_thread_wait", 100_000, || {
+        let (notifier, notify_future) = notify_future::pair::<u64>();
+        pool[i % pool.len()].send(notifier).unwrap();
+        i += 1;
+        black_box(notify_future.wait_for(Duration::from_secs(5)).unwrap().unwrap());
+    });
+}
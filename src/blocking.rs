@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+use crate::{Canceled, Elapsed, NotifyFuture, SharedNotifyFuture};
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+pub(crate) fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(ret) = Pin::new(&mut future).poll(&mut cx) {
+            return ret;
+        }
+        thread::park();
+    }
+}
+
+pub(crate) fn block_on_deadline<F: Future + Unpin>(mut future: F, deadline: Instant) -> Result<F::Output, Elapsed> {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(ret) = Pin::new(&mut future).poll(&mut cx) {
+            return Ok(ret);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Elapsed);
+        }
+        thread::park_timeout(deadline - now);
+    }
+}
+
+impl <RESULT> NotifyFuture<RESULT> {
+    pub fn wait(self) -> Result<RESULT, Canceled> {
+        block_on(self)
+    }
+
+    pub fn wait_for(self, timeout: Duration) -> Result<Result<RESULT, Canceled>, Elapsed> {
+        self.wait_until(Instant::now() + timeout)
+    }
+
+    pub fn wait_until(self, deadline: Instant) -> Result<Result<RESULT, Canceled>, Elapsed> {
+        block_on_deadline(self, deadline)
+    }
+}
+
+impl <RESULT: Clone> SharedNotifyFuture<RESULT> {
+    pub fn wait(self) -> Result<RESULT, Canceled> {
+        block_on(self)
+    }
+
+    pub fn wait_for(self, timeout: Duration) -> Result<Result<RESULT, Canceled>, Elapsed> {
+        self.wait_until(Instant::now() + timeout)
+    }
+
+    pub fn wait_until(self, deadline: Instant) -> Result<Result<RESULT, Canceled>, Elapsed> {
+        block_on_deadline(self, deadline)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::{Canceled, Elapsed};
+
+    #[test]
+    fn test_wait() {
+        let (notifier, notify_future) = crate::pair::<u32>();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            notifier.set_complete(1).unwrap();
+        });
+        assert_eq!(notify_future.wait(), Ok(1));
+    }
+
+    #[test]
+    fn test_wait_for() {
+        let (notifier, notify_future) = crate::pair::<u32>();
+        assert_eq!(notify_future.wait_for(Duration::from_millis(50)), Err(Elapsed));
+
+        let (notifier2, notify_future) = crate::pair::<u32>();
+        let shared = notify_future.shared();
+        std::thread::spawn(move || {
+            drop(notifier2);
+        });
+        assert_eq!(shared.clone().wait_for(Duration::from_secs(5)), Ok(Err(Canceled)));
+        drop(notifier);
+    }
+}
@@ -0,0 +1,214 @@
+use core::cmp::Reverse;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use crate::lock::Lock;
+use crate::waiters::Waiters;
+
+// Points into a live NotifyFutureState. The state removes its entry under the
+// LIVE lock before any of its fields are dropped, so the pointers stay valid
+// while the lock is held.
+struct Entry {
+    status: *const AtomicUsize,
+    waiters: *const Lock<Waiters>,
+    probe: *const Probe,
+}
+
+unsafe impl Send for Entry {}
+
+static LIVE: Lock<BTreeMap<usize, Entry>> = Lock::new(BTreeMap::new());
+
+pub(crate) struct Probe {
+    location: Option<&'static Location<'static>>,
+    label: Option<&'static str>,
+    // Restarted by NotifyFuture::reset so a reused state counts from its latest round.
+    created: Lock<Option<Instant>>,
+    registered: AtomicBool,
+}
+
+impl Probe {
+    pub const fn unowned() -> Self {
+        Self {
+            location: None,
+            label: None,
+            created: Lock::new(None),
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    #[track_caller]
+    pub fn new(label: Option<&'static str>) -> Self {
+        Self {
+            location: Some(Location::caller()),
+            label,
+            created: Lock::new(Some(Instant::now())),
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    // Must only be called once the owning state has reached its final address.
+    pub fn register(&self, status: &AtomicUsize, waiters: &Lock<Waiters>) {
+        let mut live = LIVE.lock();
+        live.insert(self as *const Self as usize, Entry {
+            status,
+            waiters,
+            probe: self,
+        });
+        self.registered.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        if let Some(created) = self.created.lock().as_mut() {
+            *created = Instant::now();
+        }
+    }
+
+    pub fn unregister(&mut self) {
+        if *self.registered.get_mut() {
+            LIVE.lock().remove(&(self as *const Self as usize));
+            *self.registered.get_mut() = false;
+        }
+    }
+}
+
+// A dropped or recycled state has unregistered already, this only covers a
+// probe dropped on its own.
+impl Drop for Probe {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingFuture {
+    /// Where `pair`, `labeled_pair` or the pool handed out the future.
+    pub location: &'static Location<'static>,
+    pub label: Option<&'static str>,
+    pub age: Duration,
+    /// Whether a task or thread is currently parked waiting for the result.
+    pub parked: bool,
+}
+
+impl fmt::Display for PendingFuture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.location)?;
+        if let Some(label) = self.label {
+            write!(f, " [{}]", label)?;
+        }
+        write!(f, " pending for {:?}", self.age)?;
+        if self.parked {
+            write!(f, ", waiter parked")?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the futures still waiting for a notifier after `older_than`, oldest first.
+pub fn dump(older_than: Duration) -> Vec<PendingFuture> {
+    let live = LIVE.lock();
+    let mut pending: Vec<PendingFuture> = live.values().filter_map(|entry| {
+        let (status, waiters, probe) = unsafe { (&*entry.status, &*entry.waiters, &*entry.probe) };
+        let location = probe.location?;
+        let age = (*probe.created.lock())?.elapsed();
+        if age < older_than || !crate::state::is_pending(status.load(Ordering::Acquire)) {
+            return None;
+        }
+        Some(PendingFuture {
+            location,
+            label: probe.label,
+            age,
+            // A waker dropped under a waiter lock can drop another state and
+            // wait for LIVE, so never block on it here; a busy list counts as parked.
+            parked: waiters.try_lock().map(|waiters| !waiters.is_empty()).unwrap_or(true),
+        })
+    }).collect();
+    drop(live);
+    pending.sort_by_key(|pending| Reverse(pending.age));
+    pending
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::diagnostics::dump;
+
+    #[test]
+    fn test_dump() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::labeled_pair::<u32>("forgotten");
+            let line = line!() - 1;
+            let handle = async_std::task::spawn(notify_future);
+            let (done_notifier, done_future) = crate::labeled_pair::<u32>("done");
+            done_notifier.set_complete(1).unwrap();
+            async_std::task::sleep(Duration::from_millis(50)).await;
+
+            let pending: Vec<_> = dump(Duration::from_millis(20)).into_iter()
+                .filter(|pending| pending.location.file() == file!())
+                .collect();
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].label, Some("forgotten"));
+            assert_eq!(pending[0].location.line(), line);
+            assert!(pending[0].parked);
+            assert!(pending[0].age >= Duration::from_millis(50));
+            assert!(pending[0].to_string().contains("[forgotten] pending for"));
+            assert!(dump(Duration::from_secs(3600)).iter().all(|pending| pending.location.file() != file!()));
+
+            notifier.set_complete(2).unwrap();
+            assert_eq!(handle.await, Ok(2));
+            assert_eq!(done_future.await, Ok(1));
+            assert!(dump(Duration::ZERO).iter().all(|pending| pending.location.file() != file!()));
+        });
+    }
+
+    #[test]
+    fn test_dump_after_reset() {
+        let (notifier, mut notify_future) = crate::labeled_pair::<u32>("reused");
+        std::thread::sleep(Duration::from_millis(50));
+        notifier.set_complete(1).unwrap();
+        assert_eq!(notify_future.try_take(), Ok(Some(1)));
+        notify_future.reset().unwrap();
+
+        let reused = |older_than| dump(older_than).into_iter().filter(|pending| pending.label == Some("reused")).count();
+        assert_eq!(reused(Duration::ZERO), 1);
+        assert_eq!(reused(Duration::from_millis(40)), 0);
+    }
+
+    #[test]
+    fn test_dump_while_dropping() {
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let tmp_stop = stop.clone();
+        let dumper = std::thread::spawn(move || {
+            while !tmp_stop.load(std::sync::atomic::Ordering::Relaxed) {
+                let _ = dump(Duration::ZERO);
+            }
+        });
+        for _ in 0..2000 {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            drop(notify_future);
+            drop(notifier);
+        }
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        dumper.join().unwrap();
+    }
+
+    #[test]
+    fn test_dump_while_recycling() {
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let tmp_stop = stop.clone();
+        let dumper = std::thread::spawn(move || {
+            while !tmp_stop.load(std::sync::atomic::Ordering::Relaxed) {
+                let _ = dump(Duration::ZERO);
+            }
+        });
+        let pool = crate::NotifyPool::<u32>::new(2);
+        for i in 0..2000 {
+            let (notifier, mut notify_future) = pool.pair();
+            notifier.set_complete(i).unwrap();
+            assert_eq!(notify_future.try_take(), Ok(Some(i)));
+        }
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        dumper.join().unwrap();
+    }
+}
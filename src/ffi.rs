@@ -0,0 +1,180 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ffi::{c_int, c_void};
+use core::mem::ManuallyDrop;
+use core::{ptr, slice};
+use crate::Notifier;
+
+/// Returned by the extern functions when the value was delivered.
+pub const NOTIFY_FUTURE_OK: c_int = 0;
+/// Returned when the pointer is null, the future was already completed or every
+/// receiver is gone; the notifier is released either way.
+pub const NOTIFY_FUTURE_REJECTED: c_int = -1;
+/// Returned when code run by the completion, such as an `on_complete` callback
+/// or a `map` closure, panicked; the notifier is released and the future may
+/// or may not have received the value. Without the `std` feature the panic
+/// cannot be caught and aborts instead.
+pub const NOTIFY_FUTURE_PANICKED: c_int = -2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FfiCompletion {
+    Bytes(Vec<u8>),
+    Status(i32),
+}
+
+impl <RESULT> Notifier<RESULT> {
+    /// Turns the notifier into an opaque pointer that keeps the state alive until
+    /// it is passed back to `from_raw` or one of the `notify_future_*` functions.
+    /// A pointer that never comes back leaks the state and the future never
+    /// resolves, so C code that gives up must still call `notify_future_release`.
+    pub fn into_raw(self) -> *mut c_void {
+        let this = ManuallyDrop::new(self);
+        let state = unsafe { ptr::read(&this.state) };
+        Arc::into_raw(state) as *mut c_void
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` on a notifier of the same `RESULT` type
+    /// and must not be used again afterwards.
+    pub unsafe fn from_raw(ptr: *mut c_void) -> Self {
+        Self {
+            state: Arc::from_raw(ptr as *const _),
+        }
+    }
+}
+
+// Completing and releasing run callbacks and forwarded closures on the C
+// caller's thread; a panic unwinding into an extern "C" frame aborts the process.
+#[cfg(feature = "std")]
+fn catch_panic<R>(f: impl FnOnce() -> R) -> Option<R> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).ok()
+}
+
+#[cfg(not(feature = "std"))]
+fn catch_panic<R>(f: impl FnOnce() -> R) -> Option<R> {
+    Some(f())
+}
+
+unsafe fn complete(notifier: *mut c_void, result: FfiCompletion) -> c_int {
+    if notifier.is_null() {
+        return NOTIFY_FUTURE_REJECTED;
+    }
+    let notifier = Notifier::<FfiCompletion>::from_raw(notifier);
+    catch_panic(move || match notifier.set_complete(result) {
+        Ok(()) => NOTIFY_FUTURE_OK,
+        Err(_) => NOTIFY_FUTURE_REJECTED,
+    }).unwrap_or(NOTIFY_FUTURE_PANICKED)
+}
+
+/// Completes the future with a copy of `len` bytes at `data` and releases the notifier.
+///
+/// # Safety
+///
+/// `notifier` must be null or come from `Notifier<FfiCompletion>::into_raw` and
+/// not have been released yet. `data` must be valid for `len` bytes unless `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn notify_future_complete_bytes(notifier: *mut c_void, data: *const u8, len: usize) -> c_int {
+    let bytes = if len == 0 { Vec::new() } else { slice::from_raw_parts(data, len).to_vec() };
+    complete(notifier, FfiCompletion::Bytes(bytes))
+}
+
+/// Completes the future with a status code and releases the notifier.
+///
+/// # Safety
+///
+/// Same requirements on `notifier` as `notify_future_complete_bytes`.
+#[no_mangle]
+pub unsafe extern "C" fn notify_future_complete_status(notifier: *mut c_void, status: i32) -> c_int {
+    complete(notifier, FfiCompletion::Status(status))
+}
+
+/// Releases the notifier without completing; the future resolves to `Canceled`
+/// once no other notifier is left.
+///
+/// # Safety
+///
+/// Same requirements on `notifier` as `notify_future_complete_bytes`.
+#[no_mangle]
+pub unsafe extern "C" fn notify_future_release(notifier: *mut c_void) {
+    if !notifier.is_null() {
+        let notifier = Notifier::<FfiCompletion>::from_raw(notifier);
+        let _ = catch_panic(move || drop(notifier));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::ptr;
+    use crate::ffi::{notify_future_complete_bytes, notify_future_complete_status, notify_future_release, FfiCompletion, NOTIFY_FUTURE_OK, NOTIFY_FUTURE_REJECTED};
+    #[cfg(feature = "std")]
+    use crate::ffi::NOTIFY_FUTURE_PANICKED;
+
+    #[test]
+    fn test_complete_from_raw() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<FfiCompletion>();
+            // The C side typically calls back from its own thread.
+            let raw = notifier.into_raw() as usize;
+            let data = b"payload";
+            std::thread::spawn(move || {
+                assert_eq!(unsafe { notify_future_complete_bytes(raw as *mut _, data.as_ptr(), data.len()) }, NOTIFY_FUTURE_OK);
+            });
+            assert_eq!(notify_future.await, Ok(FfiCompletion::Bytes(data.to_vec())));
+
+            let (notifier, notify_future) = crate::pair::<FfiCompletion>();
+            let raw = notifier.into_raw();
+            assert_eq!(unsafe { notify_future_complete_status(raw, -5) }, NOTIFY_FUTURE_OK);
+            assert_eq!(notify_future.await, Ok(FfiCompletion::Status(-5)));
+
+            let (notifier, notify_future) = crate::pair::<FfiCompletion>();
+            let raw = notifier.clone().into_raw();
+            notifier.set_complete(FfiCompletion::Status(1)).unwrap();
+            assert_eq!(unsafe { notify_future_complete_bytes(raw, ptr::null(), 0) }, NOTIFY_FUTURE_REJECTED);
+            assert_eq!(notify_future.await, Ok(FfiCompletion::Status(1)));
+            assert_eq!(unsafe { notify_future_complete_status(ptr::null_mut(), 0) }, NOTIFY_FUTURE_REJECTED);
+        });
+    }
+
+    #[test]
+    fn test_release() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<FfiCompletion>();
+            let raw = notifier.into_raw();
+            unsafe { notify_future_release(raw) };
+            assert_eq!(notify_future.await, Err(crate::Canceled));
+
+            let (notifier, notify_future) = crate::pair::<FfiCompletion>();
+            let raw = notifier.into_raw();
+            drop(notify_future);
+            assert_eq!(unsafe { notify_future_complete_status(raw, 0) }, NOTIFY_FUTURE_REJECTED);
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let notifier = unsafe { crate::Notifier::<u32>::from_raw(notifier.into_raw()) };
+            notifier.set_complete(3).unwrap();
+            assert_eq!(notify_future.await, Ok(3));
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_panic_not_unwound_into_c() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<FfiCompletion>();
+            notify_future.on_complete(|_| panic!("callback failed"));
+            let raw = notifier.into_raw();
+            assert_eq!(unsafe { notify_future_complete_status(raw, 7) }, NOTIFY_FUTURE_PANICKED);
+            assert_eq!(notify_future.await, Ok(FfiCompletion::Status(7)));
+
+            let (notifier, notify_future) = crate::pair::<FfiCompletion>();
+            let (mapped_notifier, mapped_future) = crate::pair::<u32>();
+            notify_future.then_call(move |_| {
+                let _notifier = mapped_notifier;
+                panic!("forwarded closure failed");
+            });
+            let raw = notifier.into_raw();
+            assert_eq!(unsafe { notify_future_complete_bytes(raw, ptr::null(), 0) }, NOTIFY_FUTURE_PANICKED);
+            assert_eq!(mapped_future.await, Err(crate::Canceled));
+        });
+    }
+}
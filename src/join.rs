@@ -0,0 +1,272 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use crate::{Canceled, NotifyFuture};
+use crate::lock::Lock;
+
+struct ReadyList {
+    indexes: VecDeque<usize>,
+    queued: Vec<bool>,
+    waker: Option<Waker>,
+}
+
+// Each member is polled with its own waker, so a set_complete only queues the
+// index of the member that finished and the combinator never rescans the rest.
+struct MemberWaker {
+    index: usize,
+    ready: Arc<Lock<ReadyList>>,
+}
+
+impl Wake for MemberWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let waker = {
+            let mut ready = self.ready.lock();
+            if !ready.queued[self.index] {
+                ready.queued[self.index] = true;
+                ready.indexes.push_back(self.index);
+            }
+            ready.waker.clone()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+struct Members<RESULT> {
+    futures: Vec<Option<NotifyFuture<RESULT>>>,
+    wakers: Vec<Waker>,
+    ready: Arc<Lock<ReadyList>>,
+    pending: usize,
+}
+
+impl <RESULT> Members<RESULT> {
+    fn new(futures: Vec<NotifyFuture<RESULT>>) -> Self {
+        let len = futures.len();
+        let ready = Arc::new(Lock::new(ReadyList {
+            indexes: (0..len).collect(),
+            queued: alloc::vec![true; len],
+            waker: None,
+        }));
+        let wakers = (0..len).map(|index| Waker::from(Arc::new(MemberWaker {
+            index,
+            ready: ready.clone(),
+        }))).collect();
+        Self {
+            futures: futures.into_iter().map(Some).collect(),
+            wakers,
+            ready,
+            pending: len,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.futures.len()
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<(usize, Result<RESULT, Canceled>)>> {
+        {
+            let mut ready = self.ready.lock();
+            match ready.waker.as_ref() {
+                Some(old) if old.will_wake(cx.waker()) => {}
+                _ => ready.waker = Some(cx.waker().clone()),
+            }
+        }
+        loop {
+            if self.pending == 0 {
+                return Poll::Ready(None);
+            }
+            let index = {
+                let mut ready = self.ready.lock();
+                match ready.indexes.pop_front() {
+                    Some(index) => {
+                        ready.queued[index] = false;
+                        index
+                    }
+                    None => return Poll::Pending,
+                }
+            };
+            if let Some(future) = self.futures[index].as_mut() {
+                let mut member_cx = Context::from_waker(&self.wakers[index]);
+                if let Poll::Ready(ret) = Pin::new(future).poll(&mut member_cx) {
+                    self.futures[index] = None;
+                    self.pending -= 1;
+                    return Poll::Ready(Some((index, ret)));
+                }
+            }
+        }
+    }
+
+    fn into_remaining(self) -> Vec<NotifyFuture<RESULT>> {
+        self.futures.into_iter().flatten().collect()
+    }
+}
+
+impl <RESULT> NotifyFuture<RESULT> {
+    pub fn all<I: IntoIterator<Item = NotifyFuture<RESULT>>>(futures: I) -> NotifyAll<RESULT> {
+        let members = Members::new(futures.into_iter().collect());
+        let results = (0..members.len()).map(|_| None).collect();
+        NotifyAll {
+            members,
+            results,
+        }
+    }
+
+    pub fn any<I: IntoIterator<Item = NotifyFuture<RESULT>>>(futures: I) -> NotifyAny<RESULT> {
+        NotifyAny {
+            members: Members::new(futures.into_iter().collect()),
+        }
+    }
+
+    /// Resolves to `(0, Err(Canceled), vec![])` when `futures` is empty, like
+    /// `any` resolving to `Canceled`.
+    pub fn select<I: IntoIterator<Item = NotifyFuture<RESULT>>>(futures: I) -> NotifySelect<RESULT> {
+        NotifySelect {
+            members: Some(Members::new(futures.into_iter().collect())),
+        }
+    }
+}
+
+// Resolves once every member completed, or with Canceled as soon as one of
+// them lost all its notifiers.
+pub struct NotifyAll<RESULT> {
+    members: Members<RESULT>,
+    results: Vec<Option<RESULT>>,
+}
+
+// Results are never pinned, only moved out once every member is done.
+impl<RESULT> Unpin for NotifyAll<RESULT> {}
+
+impl <RESULT> Future for NotifyAll<RESULT> {
+    type Output = Result<Vec<RESULT>, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match this.members.poll_next(cx) {
+                Poll::Ready(Some((index, Ok(result)))) => this.results[index] = Some(result),
+                Poll::Ready(Some((_, Err(Canceled)))) => return Poll::Ready(Err(Canceled)),
+                Poll::Ready(None) => {
+                    let results = core::mem::take(&mut this.results);
+                    return Poll::Ready(Ok(results.into_iter().flatten().collect()));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// Resolves with the first member that completed and its index; members whose
+// notifiers were dropped are skipped, Canceled is returned once none is left.
+pub struct NotifyAny<RESULT> {
+    members: Members<RESULT>,
+}
+
+impl <RESULT> Future for NotifyAny<RESULT> {
+    type Output = Result<(usize, RESULT), Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match this.members.poll_next(cx) {
+                Poll::Ready(Some((index, Ok(result)))) => return Poll::Ready(Ok((index, result))),
+                Poll::Ready(Some((_, Err(Canceled)))) => {}
+                Poll::Ready(None) => return Poll::Ready(Err(Canceled)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// Resolves with the first member to finish either way, together with the
+// members that are still pending. Without members there is nothing to wait
+// for and it resolves to Canceled right away.
+pub struct NotifySelect<RESULT> {
+    members: Option<Members<RESULT>>,
+}
+
+impl <RESULT> Future for NotifySelect<RESULT> {
+    type Output = (usize, Result<RESULT, Canceled>, Vec<NotifyFuture<RESULT>>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let members = this.members.as_mut().expect("NotifySelect polled after completion");
+        match members.poll_next(cx) {
+            Poll::Ready(Some((index, ret))) => {
+                let remaining = this.members.take().unwrap().into_remaining();
+                Poll::Ready((index, ret, remaining))
+            }
+            Poll::Ready(None) => {
+                this.members = None;
+                Poll::Ready((0, Err(Canceled), Vec::new()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::{Canceled, NotifyFuture};
+
+    #[test]
+    fn test_all() {
+        async_std::task::block_on(async {
+            let (notifiers, futures): (Vec<_>, Vec<_>) = (0..4).map(|_| crate::pair::<u32>()).unzip();
+            async_std::task::spawn(async move {
+                for (i, notifier) in notifiers.into_iter().enumerate().rev() {
+                    async_std::task::sleep(Duration::from_millis(10)).await;
+                    notifier.set_complete(i as u32).unwrap();
+                }
+            });
+            assert_eq!(NotifyFuture::all(futures).await, Ok(vec![0, 1, 2, 3]));
+
+            let (notifier, future1) = crate::pair::<u32>();
+            let (_notifier, future2) = crate::pair::<u32>();
+            drop(notifier);
+            assert_eq!(NotifyFuture::all(vec![future1, future2]).await, Err(Canceled));
+            assert_eq!(NotifyFuture::<u32>::all(Vec::new()).await, Ok(Vec::new()));
+        });
+    }
+
+    #[test]
+    fn test_any_and_select() {
+        async_std::task::block_on(async {
+            let (notifier0, future0) = crate::pair::<u32>();
+            let (notifier1, future1) = crate::pair::<u32>();
+            let (notifier2, future2) = crate::pair::<u32>();
+            drop(notifier0);
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(20)).await;
+                notifier2.set_complete(2).unwrap();
+            });
+            assert_eq!(NotifyFuture::any(vec![future0, future1, future2]).await, Ok((2, 2)));
+
+            let (notifier3, future3) = crate::pair::<u32>();
+            let (notifier4, future4) = crate::pair::<u32>();
+            drop(notifier1);
+            notifier4.set_complete(4).unwrap();
+            let (index, ret, remaining) = NotifyFuture::select(vec![future3, future4]).await;
+            assert_eq!((index, ret), (1, Ok(4)));
+            assert_eq!(remaining.len(), 1);
+            notifier3.set_complete(3).unwrap();
+            let (index, ret, remaining) = NotifyFuture::select(remaining).await;
+            assert_eq!((index, ret), (0, Ok(3)));
+            assert!(remaining.is_empty());
+
+            assert_eq!(NotifyFuture::<u32>::any(Vec::new()).await, Err(Canceled));
+            let (_, ret, remaining) = NotifyFuture::<u32>::select(Vec::new()).await;
+            assert_eq!(ret, Err(Canceled));
+            assert!(remaining.is_empty());
+        });
+    }
+}
@@ -0,0 +1,219 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use alloc::sync::Arc;
+use crate::lock::Lock;
+use crate::state::NotifyFutureState;
+
+struct LatchInner {
+    count: AtomicUsize,
+    state: NotifyFutureState<()>,
+}
+
+pub struct NotifyLatch {
+    inner: Arc<LatchInner>,
+}
+
+impl NotifyLatch {
+    pub fn new(count: usize) -> Self {
+        let inner = LatchInner {
+            count: AtomicUsize::new(count),
+            state: NotifyFutureState::new_unowned(),
+        };
+        if count == 0 {
+            let _ = inner.state.set_complete(());
+        }
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    // Returns true for the call that brought the count to zero.
+    pub fn count_down(&self) -> bool {
+        let ret = self.inner.count.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| count.checked_sub(1));
+        if ret == Ok(1) {
+            let _ = self.inner.state.set_complete(());
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.inner.count.load(Ordering::Acquire)
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.inner.state.is_complete()
+    }
+
+    pub fn wait(&self) -> LatchWait {
+        LatchWait {
+            inner: self.inner.clone(),
+            waiter_key: None,
+        }
+    }
+}
+
+impl Clone for NotifyLatch {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone()
+        }
+    }
+}
+
+pub struct LatchWait {
+    inner: Arc<LatchInner>,
+    waiter_key: Option<usize>,
+}
+
+impl Future for LatchWait {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.inner.state.poll_peek(&mut this.waiter_key, cx.waker()) {
+            Some(_) => Poll::Ready(()),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for LatchWait {
+    fn drop(&mut self) {
+        self.inner.state.remove_waiter(&mut self.waiter_key);
+    }
+}
+
+struct BarrierGeneration {
+    arrived: usize,
+    state: Arc<NotifyFutureState<()>>,
+}
+
+// Each round gets its own notify state; the last arrival completes it and
+// installs a fresh one, so the barrier can be reused indefinitely.
+pub struct NotifyBarrier {
+    parties: usize,
+    generation: Lock<BarrierGeneration>,
+}
+
+impl NotifyBarrier {
+    pub fn new(parties: usize) -> Self {
+        Self {
+            parties,
+            generation: Lock::new(BarrierGeneration {
+                arrived: 0,
+                state: Arc::new(NotifyFutureState::new_unowned()),
+            }),
+        }
+    }
+
+    pub fn wait(&self) -> BarrierWait {
+        let mut generation = self.generation.lock();
+        generation.arrived += 1;
+        if generation.arrived >= self.parties {
+            let state = core::mem::replace(&mut generation.state, Arc::new(NotifyFutureState::new_unowned()));
+            generation.arrived = 0;
+            drop(generation);
+            let _ = state.set_complete(());
+            BarrierWait {
+                state,
+                leader: true,
+                waiter_key: None,
+            }
+        } else {
+            BarrierWait {
+                state: generation.state.clone(),
+                leader: false,
+                waiter_key: None,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult {
+    leader: bool,
+}
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.leader
+    }
+}
+
+pub struct BarrierWait {
+    state: Arc<NotifyFutureState<()>>,
+    leader: bool,
+    waiter_key: Option<usize>,
+}
+
+impl Future for BarrierWait {
+    type Output = BarrierWaitResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.state.poll_peek(&mut this.waiter_key, cx.waker()) {
+            Some(_) => Poll::Ready(BarrierWaitResult { leader: this.leader }),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for BarrierWait {
+    fn drop(&mut self) {
+        self.state.remove_waiter(&mut self.waiter_key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::{NotifyBarrier, NotifyLatch};
+
+    #[test]
+    fn test_latch() {
+        async_std::task::block_on(async {
+            let latch = NotifyLatch::new(3);
+            for i in 0..3 {
+                let latch = latch.clone();
+                async_std::task::spawn(async move {
+                    async_std::task::sleep(Duration::from_millis(10 * i)).await;
+                    latch.count_down();
+                });
+            }
+            latch.wait().await;
+            assert_eq!(latch.count(), 0);
+            assert!(latch.is_released());
+            assert!(!latch.count_down());
+
+            NotifyLatch::new(0).wait().await;
+        });
+    }
+
+    #[test]
+    fn test_barrier() {
+        async_std::task::block_on(async {
+            let barrier = Arc::new(NotifyBarrier::new(3));
+            for _ in 0..2 {
+                let mut handles = Vec::new();
+                for _ in 0..3 {
+                    let barrier = barrier.clone();
+                    handles.push(async_std::task::spawn(async move {
+                        barrier.wait().await.is_leader()
+                    }));
+                }
+                let mut leaders = 0;
+                for handle in handles {
+                    if handle.await {
+                        leaders += 1;
+                    }
+                }
+                assert_eq!(leaders, 1);
+            }
+        });
+    }
+}
@@ -1,86 +1,599 @@
-use std::future::Future;
-use std::sync::{Mutex, Arc};
-use std::pin::Pin;
-use std::task::{Poll, Context, Waker};
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-struct NotifyFutureState<RESULT> {
-    waker: Option<Waker>,
-    result: Option<RESULT>,
-}
+extern crate alloc;
+
+#[cfg(not(any(feature = "std", feature = "spin", feature = "critical-section")))]
+compile_error!("notify-future needs the `std`, `spin` or `critical-section` feature for locking");
+
+use core::fmt;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::pin::Pin;
+use core::ptr;
+use core::task::{Poll, Context};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use state::NotifyFutureState;
+
+#[cfg(feature = "std")]
+mod blocking;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod join;
+mod latch;
+mod lock;
+mod pool;
+#[cfg(feature = "std")]
+mod registry;
+mod result;
+mod state;
+mod static_notify;
+mod stream;
+#[cfg(feature = "std")]
+mod timeout;
+mod trace;
+mod waiters;
+mod watch;
+
+pub use join::{NotifyAll, NotifyAny, NotifySelect};
+pub use latch::{BarrierWait, BarrierWaitResult, LatchWait, NotifyBarrier, NotifyLatch};
+pub use pool::NotifyPool;
+pub use result::{result_pair, NotifyError, NotifyResultFuture};
+pub use static_notify::{StaticNotify, StaticNotifier, StaticNotifyFuture};
+pub use stream::{stream_pair, NotifyStream, StreamBuffer, StreamNotifier};
+pub use watch::{Changed, NotifyWatch, WatchReceiver, WatchRef};
+#[cfg(feature = "std")]
+pub use registry::{NotifyRegistry, RegistryFuture};
+#[cfg(feature = "std")]
+pub use result::NotifyResultTimeout;
+#[cfg(feature = "std")]
+pub use timeout::{Elapsed, Timeout};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
 
-impl <RESULT> NotifyFutureState<RESULT> {
-    pub fn new() -> Arc<Mutex<NotifyFutureState<RESULT>>> {
-        Arc::new(Mutex::new(NotifyFutureState {
-            waker: None,
-            result: None
-        }))
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "notifier dropped without completing")
     }
+}
 
-    pub fn set_complete(state: &Arc<Mutex<NotifyFutureState<RESULT>>>, result: RESULT) {
-        let mut state = state.lock().unwrap();
-        state.result = Some(result);
-        if state.waker.is_some() {
-            state.waker.take().unwrap().wake();
-        }
+#[cfg(feature = "std")]
+impl std::error::Error for Canceled {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetError;
+
+impl fmt::Display for ResetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "notify state still has waiters or a completion in progress")
     }
 }
 
-pub struct NotifyFuture<RESULT> {
-    state:Arc<Mutex<NotifyFutureState<RESULT>>>
+#[cfg(feature = "std")]
+impl std::error::Error for ResetError {}
+
+#[track_caller]
+pub fn pair<RESULT>() -> (Notifier<RESULT>, NotifyFuture<RESULT>) {
+    let state = NotifyFutureState::new_shared(None);
+    (Notifier { state: state.clone() }, NotifyFuture { state, waiter_key: None })
+}
+
+/// Like `pair`, with `label` attached to tracing events and diagnostics dumps.
+#[track_caller]
+pub fn labeled_pair<RESULT>(label: &'static str) -> (Notifier<RESULT>, NotifyFuture<RESULT>) {
+    let state = NotifyFutureState::new_shared(Some(label));
+    (Notifier { state: state.clone() }, NotifyFuture { state, waiter_key: None })
 }
 
-impl<RESULT> Clone for NotifyFuture<RESULT> {
+pub struct Notifier<RESULT> {
+    state: Arc<NotifyFutureState<RESULT>>
+}
+
+impl<RESULT> Clone for Notifier<RESULT> {
     fn clone(&self) -> Self {
+        self.state.add_notifier();
         Self {
             state: self.state.clone()
         }
     }
 }
 
-impl <RESULT> NotifyFuture<RESULT> {
-    pub fn new() -> Self {
-        Self{
-            state: NotifyFutureState::new()
+impl <RESULT> Notifier<RESULT> {
+    /// Completes the future; only the first call wins, later ones get their value back.
+    pub fn set_complete(&self, result: RESULT) -> Result<(), RESULT> {
+        self.state.set_complete(result)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.state.is_canceled()
+    }
+
+    pub fn canceled(&self) -> Cancellation<'_, RESULT> {
+        Cancellation {
+            notifier: self,
+            waiter_key: None,
         }
     }
+}
+
+impl <RESULT: Send + 'static> Notifier<RESULT> {
+    /// Returns a notifier taking `B` that completes this one with `f` applied to its value.
+    #[track_caller]
+    pub fn contramap<B, F>(self, f: F) -> Notifier<B>
+        where B: Send + 'static, F: FnOnce(B) -> RESULT + Send + 'static
+    {
+        let (notifier, future) = pair();
+        // Nobody else receives from the derived state, so it is canceled exactly
+        // when every receiver of this one is gone.
+        let derived = Arc::downgrade(&notifier.state);
+        self.state.on_cancel(Box::new(move || {
+            if let Some(derived) = derived.upgrade() {
+                derived.cancel();
+            }
+        }));
+        future.into_state().forward(Box::new(move |ret| {
+            if let Ok(ret) = ret {
+                let _ = self.set_complete(f(ret));
+            }
+        }));
+        notifier
+    }
+}
 
-    pub fn set_complete(&self, result: RESULT) {
-        NotifyFutureState::set_complete(&self.state, result);
+impl<RESULT> Drop for Notifier<RESULT> {
+    fn drop(&mut self) {
+        self.state.drop_notifier();
+    }
+}
+
+pub struct Cancellation<'a, RESULT> {
+    notifier: &'a Notifier<RESULT>,
+    waiter_key: Option<usize>,
+}
+
+impl <RESULT> Future for Cancellation<'_, RESULT> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this.notifier.state.poll_canceled(&mut this.waiter_key, cx.waker()) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<RESULT> Drop for Cancellation<'_, RESULT> {
+    fn drop(&mut self) {
+        self.notifier.state.remove_cancel_waiter(&mut self.waiter_key);
+    }
+}
+
+pub struct NotifyFuture<RESULT> {
+    state: Arc<NotifyFutureState<RESULT>>,
+    waiter_key: Option<usize>,
+}
+
+impl <RESULT> NotifyFuture<RESULT> {
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+
+    pub fn try_take(&mut self) -> Result<Option<RESULT>, Canceled> {
+        self.state.try_take()
+    }
+
+    pub fn cancel(self) {
+        self.state.cancel();
+    }
+
+    /// Calls `f` on the completing thread before waiters see the result, or right
+    /// away if the result is already there. `RESULT: Sync` because `f` may read it
+    /// while this handle is peeked at from another thread.
+    pub fn on_complete<F>(&self, f: F) where RESULT: Sync, F: FnOnce(&RESULT) + Send + 'static {
+        self.state.add_callback(Box::new(f));
+    }
+
+    /// Returns the state to empty so the surviving notifiers can complete it again.
+    pub fn reset(&mut self) -> Result<(), ResetError> {
+        self.state.reset(&mut self.waiter_key)
+    }
+
+    // Gives up the handle without dropping its receiver count.
+    fn into_state(self) -> Arc<NotifyFutureState<RESULT>> {
+        let mut this = ManuallyDrop::new(self);
+        let mut waiter_key = this.waiter_key.take();
+        this.state.remove_waiter(&mut waiter_key);
+        unsafe { ptr::read(&this.state) }
+    }
+}
+
+impl <RESULT: Send + 'static> NotifyFuture<RESULT> {
+    /// Returns a future completed with `f` applied to this one's result.
+    #[track_caller]
+    pub fn map<B, F>(self, f: F) -> NotifyFuture<B>
+        where B: Send + 'static, F: FnOnce(RESULT) -> B + Send + 'static
+    {
+        let (notifier, future) = pair();
+        let upstream = self.into_state();
+        // The receiver count kept by into_state is released once the mapped
+        // future is canceled, which cancels this state if it was the last one.
+        let weak_upstream = Arc::downgrade(&upstream);
+        future.state.on_cancel(Box::new(move || {
+            if let Some(upstream) = weak_upstream.upgrade() {
+                upstream.drop_receiver(&mut None);
+            }
+        }));
+        upstream.forward(Box::new(move |ret| {
+            if let Ok(ret) = ret {
+                let _ = notifier.set_complete(f(ret));
+            }
+        }));
+        future
+    }
+
+    /// Calls `f` with the result on the completing thread, or right away if it is
+    /// already complete. `f` is dropped without being called if the future is canceled.
+    pub fn then_call<F>(self, f: F) where F: FnOnce(RESULT) + Send + 'static {
+        self.into_state().forward(Box::new(move |ret| {
+            if let Ok(ret) = ret {
+                f(ret);
+            }
+        }));
     }
 }
 
 impl <RESULT> Future for NotifyFuture<RESULT> {
-    type Output = RESULT;
+    type Output = Result<RESULT, Canceled>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state = self.state.lock().unwrap();
-        if state.result.is_some() {
-            return Poll::Ready(state.result.take().unwrap());
+        let this = self.get_mut();
+        match this.state.poll_take(&mut this.waiter_key, cx.waker()) {
+            Some(ret) => Poll::Ready(ret),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<RESULT> Drop for NotifyFuture<RESULT> {
+    fn drop(&mut self) {
+        self.state.drop_receiver(&mut self.waiter_key);
+    }
+}
+
+impl <RESULT: Clone> NotifyFuture<RESULT> {
+    /// Clones the result without taking it. `RESULT: Sync` because `&self` may be
+    /// shared between threads peeking at the same time.
+    pub fn peek(&self) -> Option<RESULT> where RESULT: Sync {
+        self.state.peek().cloned()
+    }
+
+    pub fn shared(mut self) -> SharedNotifyFuture<RESULT> {
+        self.state.add_receiver();
+        SharedNotifyFuture {
+            state: self.state.clone(),
+            waiter_key: self.waiter_key.take(),
+            _marker: PhantomData,
         }
+    }
+}
+
+pub struct SharedNotifyFuture<RESULT: Clone> {
+    state: Arc<NotifyFutureState<RESULT>>,
+    waiter_key: Option<usize>,
+    // clones read the result concurrently, so RESULT must also be Sync
+    _marker: PhantomData<Arc<RESULT>>,
+}
+
+impl<RESULT: Clone> Clone for SharedNotifyFuture<RESULT> {
+    fn clone(&self) -> Self {
+        self.state.add_receiver();
+        Self {
+            state: self.state.clone(),
+            waiter_key: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl <RESULT: Clone> SharedNotifyFuture<RESULT> {
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+
+    pub fn peek(&self) -> Option<RESULT> {
+        self.state.peek().cloned()
+    }
+
+    pub fn on_complete<F>(&self, f: F) where RESULT: Sync, F: FnOnce(&RESULT) + Send + 'static {
+        self.state.add_callback(Box::new(f));
+    }
+}
+
+impl <RESULT: Clone> Future for SharedNotifyFuture<RESULT> {
+    type Output = Result<RESULT, Canceled>;
 
-        if state.waker.is_none() {
-            state.waker = Some(cx.waker().clone());
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.state.poll_peek(&mut this.waiter_key, cx.waker()) {
+            Some(ret) => Poll::Ready(ret.cloned()),
+            None => Poll::Pending,
         }
-        Poll::Pending
+    }
+}
+
+impl<RESULT: Clone> Drop for SharedNotifyFuture<RESULT> {
+    fn drop(&mut self) {
+        self.state.drop_receiver(&mut self.waiter_key);
     }
 }
 
 #[cfg(test)]
 mod test {
     use std::time::Duration;
-    use crate::NotifyFuture;
 
     #[test]
     fn test() {
         async_std::task::block_on(async {
-            let notify_future = NotifyFuture::<u32>::new();
-            let tmp_future = notify_future.clone();
+            let (notifier, notify_future) = crate::pair::<u32>();
             async_std::task::spawn(async move {
                 async_std::task::sleep(Duration::from_secs(3)).await;
-                tmp_future.set_complete(1);
+                notifier.set_complete(1).unwrap();
             });
             let ret = notify_future.await;
-            assert_eq!(ret, 1);
+            assert_eq!(ret, Ok(1));
+        });
+    }
+
+    #[test]
+    fn test_complete_before_await() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            notifier.clone().set_complete(2).unwrap();
+            assert_eq!(notify_future.await, Ok(2));
+        });
+    }
+
+    #[test]
+    fn test_first_complete_wins() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let timeout_notifier = notifier.clone();
+            assert_eq!(notifier.set_complete(1), Ok(()));
+            assert_eq!(timeout_notifier.set_complete(2), Err(2));
+            assert_eq!(notify_future.await, Ok(1));
+            assert_eq!(notifier.set_complete(3), Err(3));
+            assert!(notifier.is_complete());
+        });
+    }
+
+    #[test]
+    fn test_inspect() {
+        let (notifier, mut notify_future) = crate::pair::<u32>();
+        assert!(!notify_future.is_complete());
+        assert_eq!(notify_future.peek(), None);
+        assert_eq!(notify_future.try_take(), Ok(None));
+        notifier.set_complete(1).unwrap();
+        assert!(notify_future.is_complete());
+        assert_eq!(notify_future.peek(), Some(1));
+        assert_eq!(notify_future.try_take(), Ok(Some(1)));
+        assert_eq!(notify_future.peek(), None);
+        assert!(notify_future.is_complete());
+
+        let (notifier, mut notify_future) = crate::pair::<u32>();
+        drop(notifier);
+        assert_eq!(notify_future.try_take(), Err(crate::Canceled));
+    }
+
+    #[test]
+    fn test_cancel() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            assert!(!notifier.is_canceled());
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(50)).await;
+                notify_future.cancel();
+            });
+            notifier.canceled().await;
+            assert!(notifier.is_canceled());
+            assert_eq!(notifier.set_complete(1), Err(1));
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let shared = notify_future.shared();
+            let other = shared.clone();
+            drop(shared);
+            assert!(!notifier.is_canceled());
+            drop(other);
+            notifier.canceled().await;
+        });
+    }
+
+    #[test]
+    fn test_reset() {
+        async_std::task::block_on(async {
+            let (notifier, mut notify_future) = crate::pair::<u32>();
+            for i in 0..3 {
+                let tmp_notifier = notifier.clone();
+                async_std::task::spawn(async move {
+                    async_std::task::sleep(Duration::from_millis(20)).await;
+                    tmp_notifier.set_complete(i).unwrap();
+                });
+                assert_eq!((&mut notify_future).await, Ok(i));
+                notify_future.reset().unwrap();
+                assert!(!notify_future.is_complete());
+            }
+            drop(notifier);
+            notify_future.reset().unwrap();
+            assert_eq!(notify_future.await, Err(crate::Canceled));
+        });
+    }
+
+    #[test]
+    fn test_notifier_dropped() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let tmp_notifier = notifier.clone();
+            drop(notifier);
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(100)).await;
+                drop(tmp_notifier);
+            });
+            assert_eq!(notify_future.await, Err(crate::Canceled));
+        });
+    }
+
+    #[test]
+    fn test_shared() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<String>();
+            let shared = notify_future.shared();
+            let mut handles = Vec::new();
+            for _ in 0..4 {
+                handles.push(async_std::task::spawn(shared.clone()));
+            }
+            async_std::task::sleep(Duration::from_millis(100)).await;
+            notifier.set_complete("done".to_string()).unwrap();
+            for handle in handles {
+                assert_eq!(handle.await, Ok("done".to_string()));
+            }
+            assert_eq!(shared.await, Ok("done".to_string()));
+        });
+    }
+
+    #[test]
+    fn test_map() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let mapped = notify_future.map(|v| v.to_string()).map(|s| s + "!");
+            assert!(!mapped.is_complete());
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(20)).await;
+                notifier.set_complete(7).unwrap();
+            });
+            assert_eq!(mapped.await, Ok("7!".to_string()));
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            notifier.set_complete(1).unwrap();
+            let mut mapped = notify_future.map(|v| v + 1);
+            assert!(mapped.is_complete());
+            assert_eq!(mapped.try_take(), Ok(Some(2)));
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let mapped = notify_future.map(|v| v + 1).shared();
+            drop(notifier);
+            assert_eq!(mapped.await, Err(crate::Canceled));
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let mapped = notify_future.map(|v| v + 1).map(|v| v * 2);
+            assert!(!notifier.is_canceled());
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(20)).await;
+                drop(mapped);
+            });
+            notifier.canceled().await;
+            assert_eq!(notifier.set_complete(1), Err(1));
+        });
+    }
+
+    #[test]
+    fn test_contramap() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let notifier = notifier.contramap(|s: &str| s.len() as u32);
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(20)).await;
+                notifier.set_complete("four").unwrap();
+            });
+            assert_eq!(notify_future.await, Ok(4));
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let notifier = notifier.contramap(|v: u8| v as u32);
+            assert_eq!(notifier.set_complete(1), Ok(()));
+            assert_eq!(notifier.set_complete(2), Err(2));
+            assert_eq!(notify_future.await, Ok(1));
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            drop(notifier.contramap(|v: u8| v as u32));
+            assert_eq!(notify_future.await, Err(crate::Canceled));
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let notifier = notifier.contramap(|v: u8| v as u32);
+            assert!(!notifier.is_canceled());
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(20)).await;
+                drop(notify_future);
+            });
+            notifier.canceled().await;
+            assert!(notifier.is_canceled());
+            assert_eq!(notifier.set_complete(1), Err(1));
+        });
+    }
+
+    #[test]
+    fn test_on_complete() {
+        async_std::task::block_on(async {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let tmp_tx = tx.clone();
+            notify_future.on_complete(move |v| tmp_tx.send((*v, std::thread::current().id())).unwrap());
+            assert!(rx.try_recv().is_err());
+            let completer = std::thread::spawn(move || {
+                notifier.set_complete(5).unwrap();
+                std::thread::current().id()
+            }).join().unwrap();
+            assert_eq!(rx.try_recv(), Ok((5, completer)));
+            assert_eq!(notify_future.await, Ok(5));
+
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let shared = notify_future.shared();
+            notifier.set_complete(6).unwrap();
+            shared.on_complete(move |v| tx.send((*v, std::thread::current().id())).unwrap());
+            assert_eq!(rx.try_recv(), Ok((6, std::thread::current().id())));
+            assert_eq!(shared.await, Ok(6));
+        });
+    }
+
+    #[test]
+    fn test_then_call() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (notifier, notify_future) = crate::pair::<String>();
+        let tmp_tx = tx.clone();
+        notify_future.then_call(move |v| tmp_tx.send(v).unwrap());
+        assert!(rx.try_recv().is_err());
+        notifier.set_complete("done".to_string()).unwrap();
+        assert_eq!(rx.try_recv(), Ok("done".to_string()));
+
+        let (notifier, notify_future) = crate::pair::<String>();
+        notifier.set_complete("early".to_string()).unwrap();
+        notify_future.then_call(move |v| tx.send(v).unwrap());
+        assert_eq!(rx.try_recv(), Ok("early".to_string()));
+
+        let (notifier, notify_future) = crate::pair::<String>();
+        let (drop_tx, drop_rx) = std::sync::mpsc::channel::<String>();
+        notify_future.then_call(move |v| drop_tx.send(v).unwrap());
+        drop(notifier);
+        assert!(drop_rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_dropped_waiter_unregisters() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let shared = notify_future.shared();
+            let waiter = shared.clone();
+            let _ = async_std::future::timeout(Duration::from_millis(50), waiter).await;
+            assert_eq!(notifier.state.waiter_count(), 0);
+            notifier.set_complete(3).unwrap();
+            assert_eq!(shared.await, Ok(3));
         });
     }
 }
@@ -0,0 +1,196 @@
+#[cfg(any(feature = "spin", feature = "critical-section"))]
+use core::cell::UnsafeCell;
+#[cfg(any(feature = "spin", feature = "critical-section"))]
+use core::ops::{Deref, DerefMut};
+#[cfg(all(feature = "spin", not(feature = "critical-section")))]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(any(feature = "spin", feature = "critical-section")))]
+use std::sync::{Mutex, MutexGuard, PoisonError};
+#[cfg(all(not(any(feature = "spin", feature = "critical-section")), feature = "diagnostics"))]
+use std::sync::TryLockError;
+
+// Every structure guarded by a Lock is left consistent between statements, so a
+// panic while the guard was held cannot leave it half-updated and poisoning is
+// safe to ignore.
+#[cfg(not(any(feature = "spin", feature = "critical-section")))]
+pub(crate) type LockGuard<'a, T> = MutexGuard<'a, T>;
+
+#[cfg(not(any(feature = "spin", feature = "critical-section")))]
+pub(crate) struct Lock<T> {
+    inner: Mutex<T>,
+}
+
+#[cfg(not(any(feature = "spin", feature = "critical-section")))]
+impl <T> Lock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockGuard<'_, T> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "diagnostics")]
+    pub fn try_lock(&self) -> Option<LockGuard<'_, T>> {
+        match self.inner.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
+
+// Without std the critical sections are a handful of instructions long, so a
+// plain test-and-test-and-set spin lock is enough. It must not be taken from an
+// interrupt handler: if the handler preempts a holder on the same core it spins
+// forever, which is what the `critical-section` lock below is for.
+#[cfg(all(feature = "spin", not(feature = "critical-section")))]
+pub(crate) struct Lock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// Interrupts stay masked (or the platform's global lock is held) for as long
+// as the guard lives, so a handler can never preempt a holder. The guard must
+// be dropped on the thread that took it and in reverse order, so it never
+// leaves the crate. Critical sections nest, so taking the same Lock twice
+// would hand out two `&mut T`; `locked` turns that into a panic, e.g. calling
+// NotifyWatch::set from inside borrow_with, where the std Mutex would deadlock
+// instead.
+#[cfg(feature = "critical-section")]
+pub(crate) struct Lock<T> {
+    locked: UnsafeCell<bool>,
+    value: UnsafeCell<T>,
+}
+
+#[cfg(any(feature = "spin", feature = "critical-section"))]
+unsafe impl<T: Send> Send for Lock<T> {}
+#[cfg(any(feature = "spin", feature = "critical-section"))]
+unsafe impl<T: Send> Sync for Lock<T> {}
+
+#[cfg(all(feature = "spin", not(feature = "critical-section")))]
+impl <T> Lock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockGuard<'_, T> {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            while self.locked.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+        LockGuard {
+            lock: self
+        }
+    }
+
+    #[cfg(feature = "diagnostics")]
+    pub fn try_lock(&self) -> Option<LockGuard<'_, T>> {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).ok()?;
+        Some(LockGuard {
+            lock: self
+        })
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl <T> Lock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: UnsafeCell::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> LockGuard<'_, T> {
+        let restore = unsafe { critical_section::acquire() };
+        // Only read or written inside the critical section.
+        let locked = unsafe { &mut *self.locked.get() };
+        if *locked {
+            unsafe { critical_section::release(restore) };
+            panic!("notify-future lock taken again while held");
+        }
+        *locked = true;
+        LockGuard {
+            lock: self,
+            restore,
+        }
+    }
+
+    // Entering a critical section never fails, it only waits for other cores.
+    #[cfg(feature = "diagnostics")]
+    pub fn try_lock(&self) -> Option<LockGuard<'_, T>> {
+        Some(self.lock())
+    }
+}
+
+#[cfg(any(feature = "spin", feature = "critical-section"))]
+pub(crate) struct LockGuard<'a, T> {
+    lock: &'a Lock<T>,
+    #[cfg(feature = "critical-section")]
+    restore: critical_section::RestoreState,
+}
+
+#[cfg(any(feature = "spin", feature = "critical-section"))]
+impl <T> Deref for LockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+#[cfg(any(feature = "spin", feature = "critical-section"))]
+impl <T> DerefMut for LockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+#[cfg(all(feature = "spin", not(feature = "critical-section")))]
+impl <T> Drop for LockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl <T> Drop for LockGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            *self.lock.locked.get() = false;
+            critical_section::release(self.restore);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Lock;
+
+    #[test]
+    fn test_recover_poisoned() {
+        let lock = Lock::new(1);
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut guard = lock.lock();
+            *guard = 2;
+            panic!("poison the lock");
+        }));
+        assert_eq!(*lock.lock(), 2);
+    }
+
+    #[cfg(feature = "critical-section")]
+    #[test]
+    #[should_panic(expected = "taken again while held")]
+    fn test_reentry_panics() {
+        let lock = Lock::new(1);
+        let _guard = lock.lock();
+        let _ = lock.lock();
+    }
+}
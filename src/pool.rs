@@ -0,0 +1,95 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::{Notifier, NotifyFuture};
+use crate::lock::Lock;
+use crate::state::NotifyFutureState;
+
+struct PoolSlots<RESULT> {
+    states: Vec<Arc<NotifyFutureState<RESULT>>>,
+    next: usize,
+}
+
+// The pool keeps a reference to every state it hands out; once the pool holds
+// the only one left, the state is recycled for the next pair.
+pub struct NotifyPool<RESULT> {
+    capacity: usize,
+    slots: Lock<PoolSlots<RESULT>>,
+}
+
+impl <RESULT> NotifyPool<RESULT> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            slots: Lock::new(PoolSlots {
+                states: Vec::with_capacity(capacity),
+                next: 0,
+            }),
+        }
+    }
+
+    #[track_caller]
+    pub fn pair(&self) -> (Notifier<RESULT>, NotifyFuture<RESULT>) {
+        let state = self.acquire();
+        (Notifier { state: state.clone() }, NotifyFuture { state, waiter_key: None })
+    }
+
+    #[track_caller]
+    fn acquire(&self) -> Arc<NotifyFutureState<RESULT>> {
+        let mut slots = self.slots.lock();
+        let len = slots.states.len();
+        for i in 0..len {
+            let index = (slots.next + i) % len;
+            if let Some(state) = Arc::get_mut(&mut slots.states[index]) {
+                state.recycle();
+                slots.next = (index + 1) % len;
+                let state = slots.states[index].clone();
+                state.register_probe();
+                return state;
+            }
+        }
+
+        let state = NotifyFutureState::new_shared(None);
+        if len < self.capacity {
+            slots.states.push(state.clone());
+        }
+        state
+    }
+
+    pub fn idle(&self) -> usize {
+        let slots = self.slots.lock();
+        slots.states.iter().filter(|state| Arc::strong_count(state) == 1).count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::NotifyPool;
+
+    #[test]
+    fn test_pool_recycle() {
+        async_std::task::block_on(async {
+            let pool = NotifyPool::<u32>::new(2);
+            for i in 0..10 {
+                let (notifier, future) = pool.pair();
+                let handle = async_std::task::spawn(async move {
+                    notifier.set_complete(i).unwrap();
+                });
+                assert_eq!(future.await, Ok(i));
+                handle.await;
+            }
+            assert_eq!(pool.idle(), 1);
+
+            let (notifier1, future1) = pool.pair();
+            let (notifier2, future2) = pool.pair();
+            let (notifier3, future3) = pool.pair();
+            assert_eq!(pool.idle(), 0);
+            drop(notifier1);
+            drop(notifier2);
+            drop(notifier3);
+            assert_eq!(future1.await, Err(crate::Canceled));
+            assert_eq!(pool.idle(), 1);
+            drop((future2, future3));
+            assert_eq!(pool.idle(), 2);
+        });
+    }
+}
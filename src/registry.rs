@@ -0,0 +1,190 @@
+use core::future::Future;
+use core::hash::Hash;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use crate::{pair, Canceled, Notifier, NotifyFuture};
+use crate::lock::Lock;
+
+type Entries<K, V> = Lock<HashMap<K, Notifier<V>>>;
+
+// Each outstanding key owns the notifier of its pair; the future side only keeps
+// a weak reference so a dropped registry fails every waiter.
+pub struct NotifyRegistry<K, V> {
+    entries: Arc<Entries<K, V>>,
+}
+
+impl<K, V> Clone for NotifyRegistry<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Default for NotifyRegistry<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl <K: Hash + Eq, V> NotifyRegistry<K, V> {
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(Lock::new(HashMap::new())),
+        }
+    }
+
+    /// Issues the future for `key`, handing the key back if it is already outstanding.
+    #[track_caller]
+    pub fn register(&self, key: K) -> Result<RegistryFuture<K, V>, K> where K: Clone {
+        let mut entries = self.entries.lock();
+        if entries.contains_key(&key) {
+            return Err(key);
+        }
+        let (notifier, future) = pair();
+        entries.insert(key.clone(), notifier);
+        Ok(RegistryFuture {
+            future,
+            key,
+            entries: Arc::downgrade(&self.entries),
+        })
+    }
+
+    pub fn complete(&self, key: &K, result: V) -> Result<(), V> {
+        let notifier = self.entries.lock().remove(key);
+        match notifier {
+            Some(notifier) => notifier.set_complete(result),
+            None => Err(result),
+        }
+    }
+
+    /// Fails every outstanding future with `Canceled` and returns how many there were.
+    pub fn fail_all(&self) -> usize {
+        let entries: Vec<_> = self.entries.lock().drain().collect();
+        entries.len()
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.lock().contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+}
+
+pub struct RegistryFuture<K: Hash + Eq, V> {
+    future: NotifyFuture<V>,
+    key: K,
+    entries: Weak<Entries<K, V>>,
+}
+
+// The key is never pinned, polling only touches the inner NotifyFuture.
+impl<K: Hash + Eq, V> Unpin for RegistryFuture<K, V> {}
+
+impl <K: Hash + Eq, V> RegistryFuture<K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.future.is_complete()
+    }
+
+    pub fn try_take(&mut self) -> Result<Option<V>, Canceled> {
+        self.future.try_take()
+    }
+}
+
+impl <K: Hash + Eq, V> Future for RegistryFuture<K, V> {
+    type Output = Result<V, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().future).poll(cx)
+    }
+}
+
+impl<K: Hash + Eq, V> Drop for RegistryFuture<K, V> {
+    fn drop(&mut self) {
+        let entries = match self.entries.upgrade() {
+            Some(entries) => entries,
+            None => return,
+        };
+        let mut entries = entries.lock();
+        // The key may have been completed and registered again in the meantime.
+        let ours = entries.get(&self.key)
+            .map(|notifier| Arc::ptr_eq(&notifier.state, &self.future.state))
+            .unwrap_or(false);
+        let notifier = if ours { entries.remove(&self.key) } else { None };
+        drop(entries);
+        drop(notifier);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::NotifyRegistry;
+
+    #[test]
+    fn test_complete_by_key() {
+        async_std::task::block_on(async {
+            let registry = NotifyRegistry::<u64, String>::new();
+            let first = registry.register(1).unwrap();
+            let second = registry.register(2).unwrap();
+            assert_eq!(registry.register(1).err(), Some(1));
+            assert_eq!(registry.len(), 2);
+
+            let tmp_registry = registry.clone();
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(20)).await;
+                tmp_registry.complete(&2, "two".to_string()).unwrap();
+                tmp_registry.complete(&1, "one".to_string()).unwrap();
+            });
+            assert_eq!(second.await, Ok("two".to_string()));
+            assert_eq!(first.await, Ok("one".to_string()));
+            assert!(registry.is_empty());
+            assert_eq!(registry.complete(&1, "again".to_string()), Err("again".to_string()));
+        });
+    }
+
+    #[test]
+    fn test_waiter_dropped() {
+        let registry = NotifyRegistry::<u64, u32>::new();
+        let future = registry.register(1).unwrap();
+        assert!(registry.contains(&1));
+        drop(future);
+        assert!(!registry.contains(&1));
+        assert_eq!(registry.complete(&1, 1), Err(1));
+
+        let stale = registry.register(2).unwrap();
+        registry.complete(&2, 1).unwrap();
+        let fresh = registry.register(2).unwrap();
+        drop(stale);
+        assert!(registry.contains(&2));
+        drop(fresh);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_fail_all() {
+        async_std::task::block_on(async {
+            let registry = NotifyRegistry::<u64, u32>::new();
+            let futures: Vec<_> = (0..3).map(|key| registry.register(key).unwrap()).collect();
+            assert_eq!(registry.fail_all(), 3);
+            for future in futures {
+                assert_eq!(future.await, Err(crate::Canceled));
+            }
+
+            let future = registry.register(4).unwrap();
+            drop(registry);
+            assert_eq!(future.await, Err(crate::Canceled));
+        });
+    }
+}
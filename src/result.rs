@@ -0,0 +1,195 @@
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+use crate::{Canceled, Notifier, NotifyFuture};
+#[cfg(feature = "std")]
+use crate::{Elapsed, Timeout};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyError<E> {
+    Failed(E),
+    Canceled,
+    Elapsed,
+}
+
+impl <E> From<Canceled> for NotifyError<E> {
+    fn from(_: Canceled) -> Self {
+        NotifyError::Canceled
+    }
+}
+
+#[cfg(feature = "std")]
+impl <E> From<Elapsed> for NotifyError<E> {
+    fn from(_: Elapsed) -> Self {
+        NotifyError::Elapsed
+    }
+}
+
+impl <E: fmt::Display> fmt::Display for NotifyError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::Failed(e) => write!(f, "{}", e),
+            NotifyError::Canceled => write!(f, "{}", Canceled),
+            NotifyError::Elapsed => write!(f, "deadline elapsed before completion"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl <E: std::error::Error + 'static> std::error::Error for NotifyError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NotifyError::Failed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[track_caller]
+pub fn result_pair<T, E>() -> (Notifier<Result<T, E>>, NotifyResultFuture<T, E>) {
+    let (notifier, future) = crate::pair();
+    (notifier, NotifyResultFuture::from(future))
+}
+
+impl <T, E> Notifier<Result<T, E>> {
+    pub fn set_ok(&self, value: T) -> Result<(), Result<T, E>> {
+        self.set_complete(Ok(value))
+    }
+
+    pub fn set_err(&self, err: E) -> Result<(), Result<T, E>> {
+        self.set_complete(Err(err))
+    }
+}
+
+fn flatten<T, E>(ret: Result<Result<T, E>, Canceled>) -> Result<T, NotifyError<E>> {
+    match ret {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(NotifyError::Failed(e)),
+        Err(Canceled) => Err(NotifyError::Canceled),
+    }
+}
+
+pub struct NotifyResultFuture<T, E> {
+    future: NotifyFuture<Result<T, E>>,
+}
+
+impl <T, E> From<NotifyFuture<Result<T, E>>> for NotifyResultFuture<T, E> {
+    fn from(future: NotifyFuture<Result<T, E>>) -> Self {
+        Self {
+            future
+        }
+    }
+}
+
+impl <T, E> NotifyResultFuture<T, E> {
+    pub fn is_complete(&self) -> bool {
+        self.future.is_complete()
+    }
+
+    pub fn try_take(&mut self) -> Result<Option<T>, NotifyError<E>> {
+        match self.future.try_take() {
+            Ok(Some(ret)) => flatten(Ok(ret)).map(Some),
+            Ok(None) => Ok(None),
+            Err(Canceled) => Err(NotifyError::Canceled),
+        }
+    }
+
+    pub fn cancel(self) {
+        self.future.cancel();
+    }
+
+    pub fn into_inner(self) -> NotifyFuture<Result<T, E>> {
+        self.future
+    }
+}
+
+#[cfg(feature = "std")]
+impl <T, E> NotifyResultFuture<T, E> {
+    pub fn wait_timeout(self, timeout: Duration) -> NotifyResultTimeout<T, E> {
+        self.wait_deadline(Instant::now() + timeout)
+    }
+
+    pub fn wait_deadline(self, deadline: Instant) -> NotifyResultTimeout<T, E> {
+        NotifyResultTimeout {
+            timeout: self.future.wait_deadline(deadline),
+        }
+    }
+
+    pub fn wait(self) -> Result<T, NotifyError<E>> {
+        flatten(self.future.wait())
+    }
+
+    pub fn wait_for(self, timeout: Duration) -> Result<T, NotifyError<E>> {
+        self.wait_until(Instant::now() + timeout)
+    }
+
+    pub fn wait_until(self, deadline: Instant) -> Result<T, NotifyError<E>> {
+        flatten(self.future.wait_until(deadline)?)
+    }
+}
+
+impl <T, E> Future for NotifyResultFuture<T, E> {
+    type Output = Result<T, NotifyError<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.get_mut().future).poll(cx).map(flatten)
+    }
+}
+
+#[cfg(feature = "std")]
+pub struct NotifyResultTimeout<T, E> {
+    timeout: Timeout<NotifyFuture<Result<T, E>>>,
+}
+
+#[cfg(feature = "std")]
+impl <T, E> Future for NotifyResultTimeout<T, E> {
+    type Output = Result<T, NotifyError<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.get_mut().timeout).poll(cx) {
+            Poll::Ready(Ok(ret)) => Poll::Ready(flatten(ret)),
+            Poll::Ready(Err(Elapsed)) => Poll::Ready(Err(NotifyError::Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::time::Duration;
+    use crate::NotifyError;
+
+    #[test]
+    fn test_result_future() {
+        async_std::task::block_on(async {
+            let (notifier, future) = crate::result_pair::<u32, String>();
+            notifier.set_ok(1).unwrap();
+            assert_eq!(future.await, Ok(1));
+
+            let (notifier, future) = crate::result_pair::<u32, String>();
+            notifier.set_err("failed".to_string()).unwrap();
+            assert_eq!(future.await, Err(NotifyError::Failed("failed".to_string())));
+
+            let (notifier, future) = crate::result_pair::<u32, String>();
+            drop(notifier);
+            assert_eq!(future.await, Err(NotifyError::Canceled));
+
+            let (_notifier, future) = crate::result_pair::<u32, String>();
+            assert_eq!(future.wait_timeout(Duration::from_millis(50)).await, Err(NotifyError::Elapsed));
+        });
+    }
+
+    #[test]
+    fn test_result_wait() {
+        let (notifier, future) = crate::result_pair::<u32, String>();
+        assert_eq!(notifier.set_err("failed".to_string()), Ok(()));
+        assert_eq!(notifier.set_ok(2), Err(Ok(2)));
+        assert_eq!(future.wait_for(Duration::from_secs(1)), Err(NotifyError::Failed("failed".to_string())));
+
+        let (_notifier, future) = crate::result_pair::<u32, String>();
+        assert_eq!(future.wait_for(Duration::from_millis(50)), Err(NotifyError::Elapsed));
+    }
+}
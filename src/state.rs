@@ -0,0 +1,523 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::Waker;
+use crate::{Canceled, ResetError};
+use crate::lock::Lock;
+use crate::trace::{Probe, Trace};
+use crate::waiters::Waiters;
+
+// A notifier won the race and is writing the result slot.
+const COMPLETING: usize = 1 << 0;
+// The result slot is written and visible to waiters.
+const COMPLETE: usize = 1 << 1;
+// The receiver moved the result out of the slot.
+const CONSUMED: usize = 1 << 2;
+// Every notifier has been dropped.
+const CLOSED: usize = 1 << 3;
+// Every receiver has been dropped or cancel() was called.
+const CANCELED: usize = 1 << 4;
+// At least one waker is parked in `waiters`.
+const WAITING: usize = 1 << 5;
+// At least one waker is parked in `cancel_waiters`.
+const CANCEL_WAITING: usize = 1 << 6;
+// A consumer is installed in `consumer` and takes the result instead of a receiver.
+const FORWARDING: usize = 1 << 7;
+// At least one callback was pushed to `callbacks`.
+const CALLBACKS: usize = 1 << 8;
+// The completing thread is running callbacks, the result must not be taken yet.
+const CALLING: usize = 1 << 9;
+// At least one hook was pushed to `cancel_hooks`.
+const CANCEL_HOOKS: usize = 1 << 10;
+
+pub(crate) type Consumer<RESULT> = Box<dyn FnOnce(Result<RESULT, Canceled>) + Send>;
+pub(crate) type Callback<RESULT> = Box<dyn FnOnce(&RESULT) + Send>;
+pub(crate) type CancelHook = Box<dyn FnOnce() + Send>;
+
+pub(crate) struct NotifyFutureState<RESULT> {
+    status: AtomicUsize,
+    notifier_count: AtomicUsize,
+    receiver_count: AtomicUsize,
+    result: UnsafeCell<Option<RESULT>>,
+    waiters: Lock<Waiters>,
+    cancel_waiters: Lock<Waiters>,
+    consumer: Lock<Option<Consumer<RESULT>>>,
+    callbacks: Lock<Vec<Callback<RESULT>>>,
+    cancel_hooks: Lock<Vec<CancelHook>>,
+    trace: Trace,
+    probe: Probe,
+}
+
+// The result slot is written once by the notifier that set COMPLETING and only
+// read after COMPLETE is observed with Acquire ordering. Moving the value out is
+// guarded by the CONSUMED bit and never happens while a SharedNotifyFuture
+// exists, shared readers only ever get `&RESULT`.
+// Sync only asks for RESULT: Send so non-Sync results can still cross threads;
+// every handle method that hands out `&RESULT` through `&self` must therefore
+// require RESULT: Sync itself, as NotifyFuture::peek and SharedNotifyFuture do.
+unsafe impl<RESULT: Send> Send for NotifyFutureState<RESULT> {}
+unsafe impl<RESULT: Send> Sync for NotifyFutureState<RESULT> {}
+
+fn wake_all(waiters: &Lock<Waiters>) {
+    let wakers = waiters.lock().take_all();
+    wakers.wake();
+}
+
+impl <RESULT> NotifyFutureState<RESULT> {
+    #[track_caller]
+    pub fn new_shared(label: Option<&'static str>) -> Arc<Self> {
+        let state = Arc::new(Self::with_counts(1, 1, Trace::new(label), Probe::new(label)));
+        state.register_probe();
+        state
+    }
+
+    // Borrowed receivers register themselves, so a static state starts
+    // unowned. Borrowed notifiers are not counted at all, see StaticNotifier.
+    pub const fn new_unowned() -> Self {
+        Self::with_counts(0, 0, Trace::unowned(), Probe::unowned())
+    }
+
+    const fn with_counts(notifiers: usize, receivers: usize, trace: Trace, probe: Probe) -> Self {
+        Self {
+            status: AtomicUsize::new(0),
+            notifier_count: AtomicUsize::new(notifiers),
+            receiver_count: AtomicUsize::new(receivers),
+            result: UnsafeCell::new(None),
+            waiters: Lock::new(Waiters::new()),
+            cancel_waiters: Lock::new(Waiters::new()),
+            consumer: Lock::new(None),
+            callbacks: Lock::new(Vec::new()),
+            cancel_hooks: Lock::new(Vec::new()),
+            trace,
+            probe,
+        }
+    }
+
+    pub fn set_complete(&self, result: RESULT) -> Result<(), RESULT> {
+        let prev = self.status.fetch_or(COMPLETING, Ordering::Acquire);
+        if prev & (COMPLETING | CANCELED) != 0 {
+            return Err(result);
+        }
+        unsafe {
+            *self.result.get() = Some(result);
+        }
+        // COMPLETE and CALLING are published together, so a callback registered
+        // concurrently is either queued for us or sees the result in place.
+        let mut status = self.status.load(Ordering::Relaxed);
+        loop {
+            let calling = if status & CALLBACKS != 0 { CALLING } else { 0 };
+            match self.status.compare_exchange_weak(status, status | COMPLETE | calling, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(current) => status = current,
+            }
+        }
+        self.trace.completed();
+        if status & CALLBACKS != 0 {
+            status = self.run_callbacks();
+        }
+        self.finish_complete(status);
+        Ok(())
+    }
+
+    fn finish_complete(&self, status: usize) {
+        if status & WAITING != 0 {
+            self.trace.woke();
+            wake_all(&self.waiters);
+        }
+        if status & FORWARDING != 0 {
+            self.run_consumer();
+        }
+    }
+
+    // Returns the status from clearing CALLING so the caller sees every waiter
+    // and consumer that showed up while the callbacks ran.
+    fn run_callbacks(&self) -> usize {
+        let guard = CallingGuard { state: self };
+        // Receivers leave the slot alone while CALLING is set.
+        let result = unsafe { (*self.result.get()).as_ref() };
+        loop {
+            let mut callbacks = self.callbacks.lock();
+            if callbacks.is_empty() {
+                let status = self.status.fetch_and(!CALLING, Ordering::AcqRel);
+                drop(callbacks);
+                mem::forget(guard);
+                return status;
+            }
+            let batch = mem::take(&mut *callbacks);
+            drop(callbacks);
+            if let Some(result) = result {
+                for callback in batch {
+                    callback(result);
+                }
+            }
+        }
+    }
+
+    // The caller must not be able to take the result concurrently, which holds
+    // for `&NotifyFuture` and for shared futures that never take it.
+    pub fn add_callback(&self, callback: Callback<RESULT>) {
+        let mut callbacks = self.callbacks.lock();
+        let status = self.status.fetch_or(CALLBACKS, Ordering::AcqRel);
+        if status & COMPLETE == 0 || status & CALLING != 0 {
+            callbacks.push(callback);
+            return;
+        }
+        drop(callbacks);
+        if let Ok(Some(result)) = self.peek_with_status(status) {
+            callback(result);
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.status.load(Ordering::Acquire) & COMPLETE != 0
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.status.load(Ordering::Acquire) & CANCELED != 0
+    }
+
+    pub fn add_notifier(&self) {
+        self.notifier_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn drop_notifier(&self) {
+        if self.notifier_count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.close();
+        }
+    }
+
+    // Receivers that find no result resolve to Canceled from now on.
+    pub fn close(&self) {
+        let prev = self.status.fetch_or(CLOSED, Ordering::AcqRel);
+        if prev & COMPLETING == 0 {
+            self.trace.closed();
+        }
+        if prev & WAITING != 0 {
+            self.trace.woke();
+            wake_all(&self.waiters);
+        }
+        if prev & FORWARDING != 0 {
+            self.run_consumer();
+        }
+    }
+
+    pub fn add_receiver(&self) {
+        self.receiver_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn drop_receiver(&self, waiter_key: &mut Option<usize>) {
+        self.remove_waiter(waiter_key);
+        if self.receiver_count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.cancel();
+        }
+    }
+
+    pub fn cancel(&self) {
+        let prev = self.status.fetch_or(CANCELED, Ordering::AcqRel);
+        if prev & (CANCELED | COMPLETE) == 0 {
+            self.trace.canceled();
+        }
+        if prev & CANCELED == 0 && prev & CANCEL_WAITING != 0 {
+            wake_all(&self.cancel_waiters);
+        }
+        if prev & CANCELED == 0 && prev & CANCEL_HOOKS != 0 {
+            self.run_cancel_hooks();
+        }
+    }
+
+    // Runs `hook` once the state is canceled, right away if it already is.
+    // Either cancel() sees CANCEL_HOOKS or we see CANCELED, and draining under
+    // the lock makes sure every hook runs once.
+    pub fn on_cancel(&self, hook: CancelHook) {
+        self.cancel_hooks.lock().push(hook);
+        if self.status.fetch_or(CANCEL_HOOKS, Ordering::AcqRel) & CANCELED != 0 {
+            self.run_cancel_hooks();
+        }
+    }
+
+    fn run_cancel_hooks(&self) {
+        let hooks = mem::take(&mut *self.cancel_hooks.lock());
+        for hook in hooks {
+            hook();
+        }
+    }
+
+    pub fn try_take(&self) -> Result<Option<RESULT>, Canceled> {
+        self.take_with_status(self.status.load(Ordering::Acquire))
+    }
+
+    fn take_with_status(&self, status: usize) -> Result<Option<RESULT>, Canceled> {
+        if status & CALLING != 0 {
+            return Ok(None);
+        }
+        if status & (COMPLETE | CONSUMED) == COMPLETE
+            && self.status.fetch_or(CONSUMED, Ordering::Acquire) & CONSUMED == 0 {
+            return Ok(unsafe { (*self.result.get()).take() });
+        }
+        if status & CLOSED != 0 {
+            return Err(Canceled);
+        }
+        Ok(None)
+    }
+
+    pub fn peek(&self) -> Option<&RESULT> {
+        self.peek_with_status(self.status.load(Ordering::Acquire)).ok().flatten()
+    }
+
+    fn peek_with_status(&self, status: usize) -> Result<Option<&RESULT>, Canceled> {
+        if status & (COMPLETE | CONSUMED) == COMPLETE {
+            return Ok(unsafe { (*self.result.get()).as_ref() });
+        }
+        if status & CLOSED != 0 {
+            return Err(Canceled);
+        }
+        Ok(None)
+    }
+
+    pub fn poll_take(&self, waiter_key: &mut Option<usize>, waker: &Waker) -> Option<Result<RESULT, Canceled>> {
+        self.trace.polled();
+        if let Some(ret) = self.take_with_status(self.status.load(Ordering::Acquire)).transpose() {
+            return Some(ret);
+        }
+        let status = self.register_waiter(waiter_key, waker);
+        self.take_with_status(status).transpose()
+    }
+
+    pub fn poll_peek(&self, waiter_key: &mut Option<usize>, waker: &Waker) -> Option<Result<&RESULT, Canceled>> {
+        self.trace.polled();
+        if let Some(ret) = self.peek_with_status(self.status.load(Ordering::Acquire)).transpose() {
+            return Some(ret);
+        }
+        let status = self.register_waiter(waiter_key, waker);
+        self.peek_with_status(status).transpose()
+    }
+
+    fn register_waiter(&self, waiter_key: &mut Option<usize>, waker: &Waker) -> usize {
+        let mut waiters = self.waiters.lock();
+        waiters.register(waiter_key, waker);
+        // Publishing WAITING and reading the status in one RMW means a
+        // concurrent set_complete either sees WAITING or we see COMPLETE.
+        self.status.fetch_or(WAITING, Ordering::AcqRel)
+    }
+
+    pub fn poll_canceled(&self, waiter_key: &mut Option<usize>, waker: &Waker) -> bool {
+        if self.is_canceled() {
+            return true;
+        }
+        let mut waiters = self.cancel_waiters.lock();
+        waiters.register(waiter_key, waker);
+        self.status.fetch_or(CANCEL_WAITING, Ordering::AcqRel) & CANCELED != 0
+    }
+
+    // Hands the result to `consumer` once it is complete or closed. The caller
+    // gives up its receiver, the consumer becomes the only one taking the result.
+    pub fn forward(&self, consumer: Consumer<RESULT>) {
+        *self.consumer.lock() = Some(consumer);
+        // Either set_complete/drop_notifier see FORWARDING or we see their bit,
+        // taking the consumer out of the slot makes sure it runs once.
+        let status = self.status.fetch_or(FORWARDING, Ordering::AcqRel);
+        if status & CALLING == 0 && status & (COMPLETE | CLOSED) != 0 {
+            self.run_consumer();
+        }
+    }
+
+    fn run_consumer(&self) {
+        let consumer = self.consumer.lock().take();
+        if let Some(consumer) = consumer {
+            consumer(self.try_take().and_then(|ret| ret.ok_or(Canceled)));
+        }
+    }
+
+    pub fn remove_waiter(&self, waiter_key: &mut Option<usize>) {
+        if waiter_key.is_some() {
+            self.waiters.lock().remove(waiter_key);
+        }
+    }
+
+    pub fn remove_cancel_waiter(&self, waiter_key: &mut Option<usize>) {
+        if waiter_key.is_some() {
+            self.cancel_waiters.lock().remove(waiter_key);
+        }
+    }
+
+    // The caller must be the only handle that takes the result, as NotifyFuture
+    // is. Clearing the slot happens while COMPLETING is still set so no
+    // notifier can write it concurrently.
+    pub fn reset(&self, waiter_key: &mut Option<usize>) -> Result<(), ResetError> {
+        let mut waiters = self.waiters.lock();
+        waiters.remove(waiter_key);
+        if !waiters.is_empty() {
+            return Err(ResetError);
+        }
+        let mut status = self.status.load(Ordering::Acquire);
+        loop {
+            if status & (COMPLETING | COMPLETE) == COMPLETING || status & CALLING != 0 {
+                return Err(ResetError);
+            }
+            if status & COMPLETE != 0 {
+                unsafe {
+                    *self.result.get() = None;
+                }
+            }
+            // WAITING can be dropped since the waiter list is empty and we hold its lock.
+            let next = status & (CLOSED | CANCEL_WAITING | CALLBACKS | CANCEL_HOOKS);
+            match self.status.compare_exchange_weak(status, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    self.trace.reset();
+                    self.probe.reset();
+                    return Ok(());
+                }
+                Err(current) => status = current,
+            }
+        }
+    }
+
+    // Reinitializes a state no handle refers to anymore, keeping its allocations.
+    #[track_caller]
+    pub fn recycle(&mut self) {
+        // dump() may still read `status` and `waiters` through the registry,
+        // so the old entry has to go before they are touched.
+        self.probe.unregister();
+        *self.status.get_mut() = 0;
+        *self.notifier_count.get_mut() = 1;
+        *self.receiver_count.get_mut() = 1;
+        *self.result.get_mut() = None;
+        self.waiters.lock().clear();
+        self.cancel_waiters.lock().clear();
+        *self.consumer.lock() = None;
+        self.callbacks.lock().clear();
+        self.cancel_hooks.lock().clear();
+        self.trace = Trace::new(None);
+        self.probe = Probe::new(None);
+    }
+
+    // The probe records the state's address, so this runs once it sits in its Arc.
+    pub fn register_probe(&self) {
+        self.probe.register(&self.status, &self.waiters);
+    }
+
+    #[cfg(test)]
+    pub fn waiter_count(&self) -> usize {
+        self.waiters.lock().len()
+    }
+}
+
+// Still waiting on a notifier: nothing completed, closed or canceled it yet.
+#[cfg(feature = "diagnostics")]
+pub(crate) fn is_pending(status: usize) -> bool {
+    status & (COMPLETING | CLOSED | CANCELED) == 0
+}
+
+// Only dropped when a callback panics: clears CALLING and hands the result to
+// the receivers anyway, otherwise they would wait for a flag nobody clears.
+// The callbacks that did not run yet are dropped.
+struct CallingGuard<'a, RESULT> {
+    state: &'a NotifyFutureState<RESULT>,
+}
+
+impl<RESULT> Drop for CallingGuard<'_, RESULT> {
+    fn drop(&mut self) {
+        let mut callbacks = self.state.callbacks.lock();
+        let skipped = mem::take(&mut *callbacks);
+        let status = self.state.status.fetch_and(!CALLING, Ordering::AcqRel);
+        drop(callbacks);
+        drop(skipped);
+        self.state.finish_complete(status);
+    }
+}
+
+#[cfg(any(feature = "tracing", feature = "diagnostics"))]
+impl<RESULT> Drop for NotifyFutureState<RESULT> {
+    fn drop(&mut self) {
+        // The diagnostics registry points at `status` and `waiters` until the
+        // probe is unregistered, so that has to happen here, before the fields
+        // are dropped in declaration order.
+        self.probe.unregister();
+        #[cfg(feature = "tracing")]
+        self.trace.dropped(*self.status.get_mut() & COMPLETE != 0);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::time::Duration;
+
+    #[test]
+    fn test_poisoned_waiters() {
+        let (notifier, notify_future) = crate::pair::<u32>();
+        let state = notifier.state.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = state.waiters.lock();
+            panic!("poison the waiter lock");
+        }).join();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            notifier.set_complete(1).unwrap();
+        });
+        assert_eq!(notify_future.wait(), Ok(1));
+    }
+
+    #[test]
+    fn test_panicking_notifier() {
+        let (notifier, notify_future) = crate::pair::<u32>();
+        let _ = std::thread::spawn(move || {
+            let _notifier = notifier;
+            panic!("completer failed");
+        }).join();
+        assert_eq!(notify_future.wait(), Err(crate::Canceled));
+    }
+
+    #[test]
+    fn test_concurrent_complete() {
+        for i in 0..2000u32 {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let shared = notify_future.shared();
+            let waiter = shared.clone();
+            let handle = std::thread::spawn(move || waiter.wait_for(Duration::from_secs(5)));
+            let tmp_notifier = notifier.clone();
+            std::thread::spawn(move || {
+                let _ = tmp_notifier.set_complete(i);
+            });
+            let _ = notifier.set_complete(i);
+            assert_eq!(shared.wait_for(Duration::from_secs(5)), Ok(Ok(i)));
+            assert_eq!(handle.join().unwrap(), Ok(Ok(i)));
+        }
+    }
+
+    #[test]
+    fn test_panicking_callback() {
+        let (notifier, notify_future) = crate::pair::<u32>();
+        notify_future.on_complete(|_| panic!("callback failed"));
+        let (skipped_tx, skipped) = std::sync::mpsc::channel();
+        notify_future.on_complete(move |v| skipped_tx.send(*v).unwrap());
+        let shared = notify_future.shared();
+        let waiter = shared.clone();
+        let handle = std::thread::spawn(move || waiter.wait_for(Duration::from_millis(500)));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(std::thread::spawn(move || notifier.set_complete(1)).join().is_err());
+        assert_eq!(handle.join().unwrap(), Ok(Ok(1)));
+        assert_eq!(shared.clone().wait_for(Duration::from_millis(500)), Ok(Ok(1)));
+        // the callback queued behind the panicking one is dropped, not run
+        assert!(skipped.recv().is_err());
+        let (tx, rx) = std::sync::mpsc::channel();
+        shared.on_complete(move |v| tx.send(*v).unwrap());
+        assert_eq!(rx.try_recv(), Ok(1));
+    }
+
+    #[test]
+    fn test_concurrent_callbacks() {
+        for i in 0..2000u32 {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let (tx, rx) = std::sync::mpsc::channel();
+            let handle = std::thread::spawn(move || notifier.set_complete(i));
+            notify_future.on_complete(move |v| tx.send(*v).unwrap());
+            assert_eq!(notify_future.wait_for(Duration::from_secs(5)), Ok(Ok(i)));
+            handle.join().unwrap().unwrap();
+            assert_eq!(rx.recv(), Ok(i));
+            assert!(rx.recv().is_err());
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crate::Canceled;
+use crate::state::NotifyFutureState;
+
+// Keeps the notify state inline instead of behind an Arc, handles borrow it.
+// A single parked waiter uses the inline waiter slot, so completing and
+// awaiting never allocate. Completing from an interrupt handler needs the
+// `critical-section` feature: with `spin` a handler that preempts thread code
+// holding the waiter lock on the same core would spin forever.
+pub struct StaticNotify<RESULT> {
+    state: NotifyFutureState<RESULT>,
+}
+
+impl <RESULT> StaticNotify<RESULT> {
+    pub const fn new() -> Self {
+        Self {
+            state: NotifyFutureState::new_unowned(),
+        }
+    }
+
+    pub fn notifier(&self) -> StaticNotifier<'_, RESULT> {
+        StaticNotifier {
+            state: &self.state,
+        }
+    }
+
+    pub fn future(&self) -> StaticNotifyFuture<'_, RESULT> {
+        self.state.add_receiver();
+        StaticNotifyFuture {
+            state: &self.state,
+            waiter_key: None,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+
+    // Borrowing mutably proves no handle is alive, so the state can simply be rebuilt.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl <RESULT> Default for StaticNotify<RESULT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Notifiers are taken and dropped freely, e.g. once per interrupt, so dropping
+// the last one does not close the slot the way dropping a Notifier does. The
+// waiter only resolves to Canceled after an explicit `close`.
+pub struct StaticNotifier<'a, RESULT> {
+    state: &'a NotifyFutureState<RESULT>,
+}
+
+impl<RESULT> Clone for StaticNotifier<'_, RESULT> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state,
+        }
+    }
+}
+
+impl <RESULT> StaticNotifier<'_, RESULT> {
+    pub fn set_complete(&self, result: RESULT) -> Result<(), RESULT> {
+        self.state.set_complete(result)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.state.is_canceled()
+    }
+
+    /// Gives up on completing, the future resolves to `Canceled` unless a
+    /// result is already there. Only `StaticNotify::reset` reopens the slot.
+    pub fn close(self) {
+        self.state.close();
+    }
+}
+
+pub struct StaticNotifyFuture<'a, RESULT> {
+    state: &'a NotifyFutureState<RESULT>,
+    waiter_key: Option<usize>,
+}
+
+impl <RESULT> StaticNotifyFuture<'_, RESULT> {
+    pub fn is_complete(&self) -> bool {
+        self.state.is_complete()
+    }
+
+    pub fn try_take(&mut self) -> Result<Option<RESULT>, Canceled> {
+        self.state.try_take()
+    }
+}
+
+impl <RESULT> Future for StaticNotifyFuture<'_, RESULT> {
+    type Output = Result<RESULT, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.state.poll_take(&mut this.waiter_key, cx.waker()) {
+            Some(ret) => Poll::Ready(ret),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<RESULT> Drop for StaticNotifyFuture<'_, RESULT> {
+    fn drop(&mut self) {
+        self.state.drop_receiver(&mut self.waiter_key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::StaticNotify;
+
+    static NOTIFY: StaticNotify<u32> = StaticNotify::new();
+
+    #[test]
+    fn test_static() {
+        async_std::task::block_on(async {
+            let future = NOTIFY.future();
+            std::thread::spawn(|| {
+                std::thread::sleep(Duration::from_millis(50));
+                NOTIFY.notifier().set_complete(1).unwrap();
+            });
+            assert_eq!(future.await, Ok(1));
+            assert!(NOTIFY.is_complete());
+            assert_eq!(NOTIFY.notifier().set_complete(2), Err(2));
+        });
+    }
+
+    #[test]
+    fn test_stack() {
+        let notify = StaticNotify::<u32>::new();
+        let mut future = notify.future();
+        std::thread::scope(|s| {
+            let notifier = notify.notifier();
+            s.spawn(move || notifier.set_complete(3).unwrap());
+        });
+        assert_eq!(future.try_take(), Ok(Some(3)));
+        assert_eq!(future.try_take(), Ok(None));
+        drop(future);
+
+        let mut notify = notify;
+        notify.reset();
+        assert!(!notify.is_complete());
+        let notifier = notify.notifier();
+        let mut future = notify.future();
+        notifier.set_complete(4).unwrap();
+        assert_eq!(future.try_take(), Ok(Some(4)));
+    }
+
+    #[test]
+    fn test_notifier_dropped() {
+        let notify = StaticNotify::<u32>::new();
+        let mut future = notify.future();
+        {
+            // an interrupt that has nothing to report yet
+            let notifier = notify.notifier();
+            assert!(!notifier.is_canceled());
+        }
+        assert_eq!(future.try_take(), Ok(None));
+        notify.notifier().set_complete(5).unwrap();
+        assert_eq!(future.try_take(), Ok(Some(5)));
+        drop(future);
+
+        let mut notify = notify;
+        notify.reset();
+        let mut future = notify.future();
+        notify.notifier().close();
+        assert_eq!(future.try_take(), Err(crate::Canceled));
+        assert!(!notify.is_complete());
+    }
+}
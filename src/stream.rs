@@ -0,0 +1,203 @@
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use futures_core::Stream;
+use crate::lock::Lock;
+use crate::waiters::Waiters;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamBuffer {
+    /// Only the most recent value is kept, older unread values are replaced.
+    Latest,
+    /// Up to n values are queued, set_complete hands the value back when full.
+    Bounded(usize),
+    Unbounded,
+}
+
+struct StreamInner<RESULT> {
+    queue: VecDeque<RESULT>,
+    closed: bool,
+    canceled: bool,
+    waiters: Waiters,
+}
+
+struct NotifyStreamState<RESULT> {
+    buffer: StreamBuffer,
+    notifier_count: AtomicUsize,
+    inner: Lock<StreamInner<RESULT>>,
+}
+
+impl <RESULT> NotifyStreamState<RESULT> {
+    fn new(buffer: StreamBuffer) -> Self {
+        let queue = match buffer {
+            StreamBuffer::Latest => VecDeque::with_capacity(1),
+            StreamBuffer::Bounded(n) => VecDeque::with_capacity(n),
+            StreamBuffer::Unbounded => VecDeque::new(),
+        };
+        Self {
+            buffer,
+            notifier_count: AtomicUsize::new(1),
+            inner: Lock::new(StreamInner {
+                queue,
+                closed: false,
+                canceled: false,
+                waiters: Waiters::new(),
+            }),
+        }
+    }
+
+    fn set_complete(&self, result: RESULT) -> Result<(), RESULT> {
+        let mut inner = self.inner.lock();
+        if inner.canceled {
+            return Err(result);
+        }
+        match self.buffer {
+            StreamBuffer::Latest => inner.queue.clear(),
+            StreamBuffer::Bounded(n) if inner.queue.len() >= n => return Err(result),
+            _ => {}
+        }
+        inner.queue.push_back(result);
+        let wakers = inner.waiters.take_all();
+        drop(inner);
+        wakers.wake();
+        Ok(())
+    }
+
+    fn drop_notifier(&self) {
+        if self.notifier_count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let mut inner = self.inner.lock();
+            inner.closed = true;
+            let wakers = inner.waiters.take_all();
+            drop(inner);
+            wakers.wake();
+        }
+    }
+}
+
+pub fn stream_pair<RESULT>(buffer: StreamBuffer) -> (StreamNotifier<RESULT>, NotifyStream<RESULT>) {
+    let state = Arc::new(NotifyStreamState::new(buffer));
+    (StreamNotifier { state: state.clone() }, NotifyStream { state, waiter_key: None })
+}
+
+pub struct StreamNotifier<RESULT> {
+    state: Arc<NotifyStreamState<RESULT>>,
+}
+
+impl<RESULT> Clone for StreamNotifier<RESULT> {
+    fn clone(&self) -> Self {
+        self.state.notifier_count.fetch_add(1, Ordering::Relaxed);
+        Self {
+            state: self.state.clone()
+        }
+    }
+}
+
+impl <RESULT> StreamNotifier<RESULT> {
+    pub fn set_complete(&self, result: RESULT) -> Result<(), RESULT> {
+        self.state.set_complete(result)
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.state.inner.lock().canceled
+    }
+}
+
+impl<RESULT> Drop for StreamNotifier<RESULT> {
+    fn drop(&mut self) {
+        self.state.drop_notifier();
+    }
+}
+
+pub struct NotifyStream<RESULT> {
+    state: Arc<NotifyStreamState<RESULT>>,
+    waiter_key: Option<usize>,
+}
+
+impl <RESULT> NotifyStream<RESULT> {
+    pub fn try_next(&mut self) -> Option<RESULT> {
+        self.state.inner.lock().queue.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.inner.lock().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl <RESULT> Stream for NotifyStream<RESULT> {
+    type Item = RESULT;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut inner = this.state.inner.lock();
+        if let Some(result) = inner.queue.pop_front() {
+            return Poll::Ready(Some(result));
+        }
+        if inner.closed {
+            return Poll::Ready(None);
+        }
+
+        inner.waiters.register(&mut this.waiter_key, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl<RESULT> Drop for NotifyStream<RESULT> {
+    fn drop(&mut self) {
+        let mut inner = self.state.inner.lock();
+        inner.waiters.remove(&mut self.waiter_key);
+        inner.canceled = true;
+        inner.queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::pin::Pin;
+    use std::time::Duration;
+    use futures_core::Stream;
+    use crate::{stream_pair, StreamBuffer};
+
+    #[test]
+    fn test_unbounded() {
+        async_std::task::block_on(async {
+            let (notifier, mut stream) = stream_pair::<u32>(StreamBuffer::Unbounded);
+            async_std::task::spawn(async move {
+                for i in 0..5 {
+                    async_std::task::sleep(Duration::from_millis(10)).await;
+                    notifier.set_complete(i).unwrap();
+                }
+            });
+            let mut values = Vec::new();
+            while let Some(value) = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+                values.push(value);
+            }
+            assert_eq!(values, vec![0, 1, 2, 3, 4]);
+        });
+    }
+
+    #[test]
+    fn test_latest_and_bounded() {
+        let (notifier, mut stream) = stream_pair::<u32>(StreamBuffer::Latest);
+        notifier.set_complete(1).unwrap();
+        notifier.set_complete(2).unwrap();
+        assert_eq!(stream.len(), 1);
+        assert_eq!(stream.try_next(), Some(2));
+        assert_eq!(stream.try_next(), None);
+
+        let (notifier, mut stream) = stream_pair::<u32>(StreamBuffer::Bounded(2));
+        notifier.set_complete(1).unwrap();
+        notifier.set_complete(2).unwrap();
+        assert_eq!(notifier.set_complete(3), Err(3));
+        assert_eq!(stream.try_next(), Some(1));
+        notifier.set_complete(3).unwrap();
+        drop(stream);
+        assert!(notifier.is_canceled());
+        assert_eq!(notifier.set_complete(4), Err(4));
+    }
+}
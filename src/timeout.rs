@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::mem::ManuallyDrop;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use crate::{NotifyFuture, SharedNotifyFuture};
+use crate::lock::Lock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline elapsed before completion")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+type WakerSlot = Lock<Option<Waker>>;
+
+// Ordered by deadline so the timer thread pops the earliest one first, the id
+// keeps entries with equal deadlines apart and lets a Timeout remove its own.
+type TimerKey = (Instant, u64);
+
+struct TimerQueue {
+    entries: BTreeMap<TimerKey, Weak<WakerSlot>>,
+    next_id: u64,
+}
+
+struct Timer {
+    queue: Mutex<TimerQueue>,
+    condvar: Condvar,
+}
+
+impl Timer {
+    fn get() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+        TIMER.get_or_init(|| {
+            std::thread::Builder::new()
+                .name("notify-future-timer".to_string())
+                .spawn(|| Timer::get().run())
+                .expect("failed to spawn notify-future timer thread");
+            Timer {
+                queue: Mutex::new(TimerQueue {
+                    entries: BTreeMap::new(),
+                    next_id: 0,
+                }),
+                condvar: Condvar::new(),
+            }
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TimerQueue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn add(&self, deadline: Instant, slot: &Arc<WakerSlot>) -> TimerKey {
+        let mut queue = self.lock();
+        let key = (deadline, queue.next_id);
+        queue.next_id += 1;
+        let notify = queue.entries.keys().next().map(|first| key < *first).unwrap_or(true);
+        queue.entries.insert(key, Arc::downgrade(slot));
+        if notify {
+            self.condvar.notify_one();
+        }
+        key
+    }
+
+    // Removing the earliest entry only makes the timer thread wake up early
+    // and find nothing to do, so there is no need to notify it.
+    fn remove(&self, key: &TimerKey) {
+        self.lock().entries.remove(key);
+    }
+
+    #[cfg(test)]
+    fn is_scheduled(&self, key: &TimerKey) -> bool {
+        self.lock().entries.contains_key(key)
+    }
+
+    fn run(&self) {
+        let mut queue = self.lock();
+        loop {
+            let now = Instant::now();
+            let mut expired = Vec::new();
+            while let Some(entry) = queue.entries.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                expired.push(entry.remove());
+            }
+            if !expired.is_empty() {
+                drop(queue);
+                for slot in expired {
+                    if let Some(slot) = slot.upgrade() {
+                        if let Some(waker) = slot.lock().take() {
+                            waker.wake();
+                        }
+                    }
+                }
+                queue = self.lock();
+                continue;
+            }
+            queue = match queue.entries.keys().next() {
+                Some((deadline, _)) => {
+                    let timeout = deadline.saturating_duration_since(now);
+                    self.condvar.wait_timeout(queue, timeout).unwrap_or_else(PoisonError::into_inner).0
+                }
+                None => self.condvar.wait(queue).unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+pub struct Timeout<F> {
+    future: F,
+    deadline: Instant,
+    timer: Option<(TimerKey, Arc<WakerSlot>)>,
+}
+
+impl <F> Timeout<F> {
+    pub(crate) fn new(future: F, deadline: Instant) -> Self {
+        Self {
+            future,
+            deadline,
+            timer: None,
+        }
+    }
+
+    pub fn into_inner(mut self) -> F {
+        self.cancel_timer();
+        let this = ManuallyDrop::new(self);
+        // The timer is gone, so the future is the only field left owning anything.
+        unsafe { ptr::read(&this.future) }
+    }
+
+    fn cancel_timer(&mut self) {
+        if let Some((key, _)) = self.timer.take() {
+            Timer::get().remove(&key);
+        }
+    }
+}
+
+impl <F: Future + Unpin> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(ret) = Pin::new(&mut this.future).poll(cx) {
+            this.cancel_timer();
+            return Poll::Ready(Ok(ret));
+        }
+        if Instant::now() >= this.deadline {
+            this.cancel_timer();
+            return Poll::Ready(Err(Elapsed));
+        }
+
+        match this.timer.as_ref() {
+            Some((_, slot)) => {
+                let mut waker = slot.lock();
+                match waker.as_ref() {
+                    Some(old) if old.will_wake(cx.waker()) => {}
+                    _ => *waker = Some(cx.waker().clone()),
+                }
+            }
+            None => {
+                let slot = Arc::new(Lock::new(Some(cx.waker().clone())));
+                let key = Timer::get().add(this.deadline, &slot);
+                this.timer = Some((key, slot));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<F> Drop for Timeout<F> {
+    fn drop(&mut self) {
+        self.cancel_timer();
+    }
+}
+
+impl <RESULT> NotifyFuture<RESULT> {
+    pub fn wait_timeout(self, timeout: Duration) -> Timeout<Self> {
+        self.wait_deadline(Instant::now() + timeout)
+    }
+
+    pub fn wait_deadline(self, deadline: Instant) -> Timeout<Self> {
+        Timeout::new(self, deadline)
+    }
+}
+
+impl <RESULT: Clone> SharedNotifyFuture<RESULT> {
+    pub fn wait_timeout(self, timeout: Duration) -> Timeout<Self> {
+        self.wait_deadline(Instant::now() + timeout)
+    }
+
+    pub fn wait_deadline(self, deadline: Instant) -> Timeout<Self> {
+        Timeout::new(self, deadline)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::{poll_fn, Future};
+    use std::pin::Pin;
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
+    use crate::Elapsed;
+    use crate::timeout::Timer;
+
+    #[test]
+    fn test_elapsed() {
+        async_std::task::block_on(async {
+            let (_notifier, notify_future) = crate::pair::<u32>();
+            let start = Instant::now();
+            let ret = notify_future.wait_timeout(Duration::from_millis(200)).await;
+            assert_eq!(ret, Err(Elapsed));
+            assert!(start.elapsed() >= Duration::from_millis(200));
+        });
+    }
+
+    #[test]
+    fn test_complete_before_deadline() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(50)).await;
+                notifier.set_complete(1).unwrap();
+            });
+            let deadline = Instant::now() + Duration::from_secs(5);
+            assert_eq!(notify_future.wait_deadline(deadline).await, Ok(Ok(1)));
+        });
+    }
+
+    #[test]
+    fn test_timer_entry_removed() {
+        async_std::task::block_on(async {
+            let (notifier, notify_future) = crate::pair::<u32>();
+            let mut timeout = notify_future.wait_timeout(Duration::from_secs(60));
+            poll_fn(|cx| {
+                assert!(Pin::new(&mut timeout).poll(cx).is_pending());
+                Poll::Ready(())
+            }).await;
+            let key = timeout.timer.as_ref().unwrap().0;
+            assert!(Timer::get().is_scheduled(&key));
+            notifier.set_complete(1).unwrap();
+            assert_eq!((&mut timeout).await, Ok(Ok(1)));
+            assert!(!Timer::get().is_scheduled(&key));
+
+            let (_notifier, notify_future) = crate::pair::<u32>();
+            let mut timeout = notify_future.wait_timeout(Duration::from_secs(60));
+            poll_fn(|cx| {
+                assert!(Pin::new(&mut timeout).poll(cx).is_pending());
+                Poll::Ready(())
+            }).await;
+            let key = timeout.timer.as_ref().unwrap().0;
+            drop(timeout);
+            assert!(!Timer::get().is_scheduled(&key));
+        });
+    }
+}
@@ -0,0 +1,243 @@
+// Instrumentation hooks called by NotifyFutureState. Without the `tracing`
+// feature every hook is an empty inline function on a zero-sized type, the
+// same goes for Probe without the `diagnostics` feature.
+
+#[cfg(feature = "tracing")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "tracing")]
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "diagnostics"))]
+use core::sync::atomic::AtomicUsize as Status;
+#[cfg(any(feature = "tracing", not(feature = "diagnostics")))]
+use crate::lock::Lock;
+#[cfg(not(feature = "diagnostics"))]
+use crate::waiters::Waiters;
+#[cfg(feature = "diagnostics")]
+pub(crate) use crate::diagnostics::Probe;
+
+#[cfg(feature = "tracing")]
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+#[cfg(feature = "tracing")]
+pub(crate) struct Trace {
+    // 0 for states built in a const context, which also have no creation time.
+    id: usize,
+    label: Option<&'static str>,
+    created: Option<Instant>,
+    // Cleared by NotifyFuture::reset so `waited` covers the current round only.
+    first_poll: Lock<Option<Instant>>,
+}
+
+#[cfg(feature = "tracing")]
+impl Trace {
+    pub const fn unowned() -> Self {
+        Self {
+            id: 0,
+            label: None,
+            created: None,
+            first_poll: Lock::new(None),
+        }
+    }
+
+    pub fn new(label: Option<&'static str>) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        tracing::trace!(target: "notify_future", id, label, "created");
+        Self {
+            id,
+            label,
+            created: Some(Instant::now()),
+            first_poll: Lock::new(None),
+        }
+    }
+
+    pub fn polled(&self) {
+        let mut first_poll = self.first_poll.lock();
+        if first_poll.is_some() {
+            return;
+        }
+        *first_poll = Some(Instant::now());
+        drop(first_poll);
+        tracing::trace!(target: "notify_future", id = self.id, label = self.label, "first poll");
+    }
+
+    fn waited(&self) -> Option<Duration> {
+        self.first_poll.lock().map(|first_poll| first_poll.elapsed())
+    }
+
+    pub fn completed(&self) {
+        let age = self.created.map(|created| created.elapsed());
+        let waited = self.waited();
+        tracing::debug!(target: "notify_future", id = self.id, label = self.label, ?age, ?waited, "completed");
+    }
+
+    pub fn woke(&self) {
+        tracing::trace!(target: "notify_future", id = self.id, label = self.label, "waking receivers");
+    }
+
+    pub fn closed(&self) {
+        let waited = self.waited();
+        tracing::warn!(target: "notify_future", id = self.id, label = self.label, ?waited, "every notifier dropped without completing");
+    }
+
+    pub fn canceled(&self) {
+        tracing::trace!(target: "notify_future", id = self.id, label = self.label, "canceled by receiver");
+    }
+
+    pub fn reset(&self) {
+        *self.first_poll.lock() = None;
+        tracing::trace!(target: "notify_future", id = self.id, label = self.label, "reset");
+    }
+
+    pub fn dropped(&self, completed: bool) {
+        let age = self.created.map(|created| created.elapsed());
+        tracing::trace!(target: "notify_future", id = self.id, label = self.label, completed, ?age, "dropped");
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Trace;
+
+#[cfg(not(feature = "tracing"))]
+impl Trace {
+    pub const fn unowned() -> Self {
+        Self
+    }
+
+    #[inline]
+    pub fn new(_label: Option<&'static str>) -> Self {
+        Self
+    }
+
+    #[inline]
+    pub fn polled(&self) {}
+
+    #[inline]
+    pub fn completed(&self) {}
+
+    #[inline]
+    pub fn woke(&self) {}
+
+    #[inline]
+    pub fn closed(&self) {}
+
+    #[inline]
+    pub fn canceled(&self) {}
+
+    #[inline]
+    pub fn reset(&self) {}
+}
+
+#[cfg(not(feature = "diagnostics"))]
+pub(crate) struct Probe;
+
+#[cfg(not(feature = "diagnostics"))]
+impl Probe {
+    pub const fn unowned() -> Self {
+        Self
+    }
+
+    #[inline]
+    pub fn new(_label: Option<&'static str>) -> Self {
+        Self
+    }
+
+    #[inline]
+    pub fn register(&self, _status: &Status, _waiters: &Lock<Waiters>) {}
+
+    #[inline]
+    pub fn reset(&self) {}
+
+    #[inline]
+    pub fn unregister(&mut self) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Default)]
+    struct Events {
+        messages: Mutex<Vec<String>>,
+    }
+
+    struct Collect(Arc<Events>);
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Collect {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target() == "notify_future"
+        }
+
+        fn new_span(&self, _span: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0.messages.lock().unwrap().push(fields.0);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn test_events() {
+        let events = Arc::new(Events::default());
+        tracing::subscriber::with_default(Collect(events.clone()), || {
+            async_std::task::block_on(async {
+                // The default subscriber is per thread, so everything stays on this one.
+                let (notifier, mut notify_future) = crate::labeled_pair::<u32>("rpc-7");
+                let pending = async_std::future::timeout(std::time::Duration::from_millis(20), &mut notify_future).await;
+                assert!(pending.is_err());
+                notifier.set_complete(1).unwrap();
+                assert_eq!(notify_future.await, Ok(1));
+                drop(notifier);
+
+                let (notifier, _notify_future) = crate::labeled_pair::<u32>("never");
+                drop(notifier);
+
+                let (notifier, mut notify_future) = crate::labeled_pair::<u32>("reused");
+                for i in 0..2 {
+                    let pending = async_std::future::timeout(std::time::Duration::from_millis(5), &mut notify_future).await;
+                    assert!(pending.is_err());
+                    notifier.set_complete(i).unwrap();
+                    assert_eq!(notify_future.try_take(), Ok(Some(i)));
+                    notify_future.reset().unwrap();
+                }
+            });
+        });
+        let messages = events.messages.lock().unwrap();
+        let find = |label: &str, message: &str| messages.iter()
+            .any(|m| m.contains(&format!("label=\"{}\"", label)) && m.contains(&format!("message={}", message)));
+        assert!(find("rpc-7", "created"));
+        assert!(find("rpc-7", "first poll"));
+        assert!(find("rpc-7", "completed"));
+        assert!(find("rpc-7", "dropped"));
+        assert!(find("never", "every notifier dropped without completing"));
+        assert!(!find("never", "completed"));
+        assert!(find("reused", "reset"));
+        let first_polls = messages.iter()
+            .filter(|m| m.contains("label=\"reused\"") && m.contains("message=first poll"))
+            .count();
+        assert_eq!(first_polls, 2);
+    }
+}
@@ -0,0 +1,154 @@
+use core::task::Waker;
+use alloc::vec::Vec;
+
+// Key 0 is stored inline so a single parked waiter never allocates, further
+// waiters go to a slab whose keys are offset by one.
+pub(crate) struct Waiters {
+    first: Option<Waker>,
+    first_used: bool,
+    entries: Vec<Option<Waker>>,
+    free: Vec<usize>,
+}
+
+impl Waiters {
+    pub const fn new() -> Self {
+        Self {
+            first: None,
+            first_used: false,
+            entries: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn register(&mut self, key: &mut Option<usize>, waker: &Waker) {
+        match *key {
+            Some(k) => {
+                let entry = if k == 0 {
+                    &mut self.first
+                } else {
+                    &mut self.entries[k - 1]
+                };
+                match entry {
+                    Some(old) if old.will_wake(waker) => {}
+                    _ => *entry = Some(waker.clone()),
+                }
+            }
+            None if !self.first_used => {
+                self.first_used = true;
+                self.first = Some(waker.clone());
+                *key = Some(0);
+            }
+            None => {
+                let k = match self.free.pop() {
+                    Some(k) => {
+                        self.entries[k] = Some(waker.clone());
+                        k
+                    }
+                    None => {
+                        self.entries.push(Some(waker.clone()));
+                        self.entries.len() - 1
+                    }
+                };
+                *key = Some(k + 1);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &mut Option<usize>) {
+        match key.take() {
+            Some(0) => {
+                self.first = None;
+                self.first_used = false;
+            }
+            Some(k) => {
+                self.entries[k - 1] = None;
+                self.free.push(k - 1);
+            }
+            None => {}
+        }
+    }
+
+    // Wakers are handed back rather than invoked so callers can wake them after
+    // releasing their lock.
+    pub fn take_all(&mut self) -> WakeList {
+        WakeList {
+            first: self.first.take(),
+            rest: self.entries.iter_mut().filter_map(|entry| entry.take()).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.first_used && self.entries.len() == self.free.len()
+    }
+
+    // Forgets every key but keeps the allocations for reuse.
+    pub fn clear(&mut self) {
+        self.first = None;
+        self.first_used = false;
+        self.entries.clear();
+        self.free.clear();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.first_used as usize + self.entries.len() - self.free.len()
+    }
+}
+
+pub(crate) struct WakeList {
+    first: Option<Waker>,
+    rest: Vec<Waker>,
+}
+
+impl WakeList {
+    pub fn wake(self) {
+        if let Some(waker) = self.first {
+            waker.wake();
+        }
+        for waker in self.rest {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Wake, Waker};
+    use super::Waiters;
+
+    struct CountWaker(AtomicUsize);
+
+    impl Wake for CountWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_register_remove() {
+        let counter = Arc::new(CountWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut waiters = Waiters::new();
+        let mut key1 = None;
+        let mut key2 = None;
+        waiters.register(&mut key1, &waker);
+        waiters.register(&mut key1, &waker);
+        waiters.register(&mut key2, &waker);
+        assert_eq!(waiters.len(), 2);
+        waiters.remove(&mut key2);
+        assert_eq!(waiters.len(), 1);
+        assert!(key2.is_none());
+        waiters.take_all().wake();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        let mut key3 = None;
+        waiters.register(&mut key3, &waker);
+        assert_eq!(key3, Some(1));
+        waiters.remove(&mut key1);
+        let mut key4 = None;
+        waiters.register(&mut key4, &waker);
+        assert_eq!(key4, Some(0));
+    }
+}
@@ -0,0 +1,334 @@
+use core::future::Future;
+#[cfg(feature = "critical-section")]
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use alloc::sync::Arc;
+use crate::Canceled;
+use crate::lock::Lock;
+#[cfg(not(feature = "critical-section"))]
+use crate::lock::LockGuard;
+use crate::waiters::Waiters;
+
+struct WatchInner<T> {
+    value: T,
+    version: u64,
+    closed: bool,
+    waiters: Waiters,
+}
+
+struct WatchState<T> {
+    sender_count: AtomicUsize,
+    inner: Lock<WatchInner<T>>,
+}
+
+#[cfg(not(feature = "critical-section"))]
+impl <T> WatchState<T> {
+    fn borrow(&self) -> WatchRef<'_, T> {
+        WatchRef {
+            guard: self.inner.lock(),
+        }
+    }
+}
+
+// A critical section has to be released on the thread that entered it and in
+// reverse order, neither of which holds for a guard handed to the caller, so
+// borrows copy the value out instead.
+#[cfg(feature = "critical-section")]
+impl <T: Clone> WatchState<T> {
+    fn borrow(&self) -> WatchRef<'_, T> {
+        let inner = self.inner.lock();
+        WatchRef {
+            value: inner.value.clone(),
+            version: inner.version,
+            _marker: PhantomData,
+        }
+    }
+}
+
+pub struct NotifyWatch<T> {
+    state: Arc<WatchState<T>>,
+}
+
+impl <T> NotifyWatch<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: Arc::new(WatchState {
+                sender_count: AtomicUsize::new(1),
+                inner: Lock::new(WatchInner {
+                    value,
+                    version: 0,
+                    closed: false,
+                    waiters: Waiters::new(),
+                }),
+            }),
+        }
+    }
+
+    pub fn set(&self, value: T) -> T {
+        let mut inner = self.state.inner.lock();
+        let old = core::mem::replace(&mut inner.value, value);
+        inner.version += 1;
+        let wakers = inner.waiters.take_all();
+        drop(inner);
+        wakers.wake();
+        old
+    }
+
+    pub fn subscribe(&self) -> WatchReceiver<T> {
+        let version = self.state.inner.lock().version;
+        WatchReceiver {
+            state: self.state.clone(),
+            version,
+        }
+    }
+
+    /// Runs `f` on the current value while holding the lock, without cloning it.
+    pub fn borrow_with<R, F>(&self, f: F) -> R where F: FnOnce(&T) -> R {
+        f(&self.state.inner.lock().value)
+    }
+
+    pub fn version(&self) -> u64 {
+        self.state.inner.lock().version
+    }
+}
+
+#[cfg(not(feature = "critical-section"))]
+impl <T> NotifyWatch<T> {
+    pub fn borrow(&self) -> WatchRef<'_, T> {
+        self.state.borrow()
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl <T: Clone> NotifyWatch<T> {
+    /// Returns a copy of the current value, see `borrow_with` for values that are not Clone.
+    pub fn borrow(&self) -> WatchRef<'_, T> {
+        self.state.borrow()
+    }
+}
+
+impl<T> Clone for NotifyWatch<T> {
+    fn clone(&self) -> Self {
+        self.state.sender_count.fetch_add(1, Ordering::Relaxed);
+        Self {
+            state: self.state.clone()
+        }
+    }
+}
+
+impl<T> Drop for NotifyWatch<T> {
+    fn drop(&mut self) {
+        if self.state.sender_count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let mut inner = self.state.inner.lock();
+            inner.closed = true;
+            let wakers = inner.waiters.take_all();
+            drop(inner);
+            wakers.wake();
+        }
+    }
+}
+
+#[cfg(not(feature = "critical-section"))]
+pub struct WatchRef<'a, T> {
+    guard: LockGuard<'a, WatchInner<T>>,
+}
+
+#[cfg(feature = "critical-section")]
+pub struct WatchRef<'a, T> {
+    value: T,
+    version: u64,
+    _marker: PhantomData<&'a T>,
+}
+
+#[cfg(not(feature = "critical-section"))]
+impl <T> WatchRef<'_, T> {
+    pub fn version(&self) -> u64 {
+        self.guard.version
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl <T> WatchRef<'_, T> {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl <T> Deref for WatchRef<'_, T> {
+    type Target = T;
+
+    #[cfg(not(feature = "critical-section"))]
+    fn deref(&self) -> &T {
+        &self.guard.value
+    }
+
+    #[cfg(feature = "critical-section")]
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+pub struct WatchReceiver<T> {
+    state: Arc<WatchState<T>>,
+    version: u64,
+}
+
+#[cfg(not(feature = "critical-section"))]
+impl <T> WatchReceiver<T> {
+    pub fn borrow(&self) -> WatchRef<'_, T> {
+        self.state.borrow()
+    }
+
+    pub fn borrow_and_update(&mut self) -> WatchRef<'_, T> {
+        let value = self.state.borrow();
+        self.version = value.version();
+        value
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl <T: Clone> WatchReceiver<T> {
+    pub fn borrow(&self) -> WatchRef<'_, T> {
+        self.state.borrow()
+    }
+
+    pub fn borrow_and_update(&mut self) -> WatchRef<'_, T> {
+        let value = self.state.borrow();
+        self.version = value.version();
+        value
+    }
+}
+
+impl<T> Clone for WatchReceiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            version: self.version,
+        }
+    }
+}
+
+impl <T> WatchReceiver<T> {
+    /// Runs `f` on the current value while holding the lock, without cloning it.
+    pub fn borrow_with<R, F>(&self, f: F) -> R where F: FnOnce(&T) -> R {
+        f(&self.state.inner.lock().value)
+    }
+
+    pub fn has_changed(&self) -> bool {
+        self.state.inner.lock().version != self.version
+    }
+
+    pub fn changed(&mut self) -> Changed<'_, T> {
+        Changed {
+            receiver: self,
+            waiter_key: None,
+        }
+    }
+}
+
+pub struct Changed<'a, T> {
+    receiver: &'a mut WatchReceiver<T>,
+    waiter_key: Option<usize>,
+}
+
+impl <T> Future for Changed<'_, T> {
+    type Output = Result<(), Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut inner = this.receiver.state.inner.lock();
+        if inner.version != this.receiver.version {
+            this.receiver.version = inner.version;
+            inner.waiters.remove(&mut this.waiter_key);
+            return Poll::Ready(Ok(()));
+        }
+        if inner.closed {
+            inner.waiters.remove(&mut this.waiter_key);
+            return Poll::Ready(Err(Canceled));
+        }
+
+        inner.waiters.register(&mut this.waiter_key, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Changed<'_, T> {
+    fn drop(&mut self) {
+        if self.waiter_key.is_some() {
+            self.receiver.state.inner.lock().waiters.remove(&mut self.waiter_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::NotifyWatch;
+
+    #[test]
+    fn test_watch() {
+        async_std::task::block_on(async {
+            let watch = NotifyWatch::new(0u32);
+            let mut handles = Vec::new();
+            for _ in 0..3 {
+                let mut receiver = watch.subscribe();
+                handles.push(async_std::task::spawn(async move {
+                    let mut seen = Vec::new();
+                    while receiver.changed().await.is_ok() {
+                        seen.push(*receiver.borrow());
+                    }
+                    seen
+                }));
+            }
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            for i in 1..=3 {
+                watch.set(i);
+                async_std::task::sleep(Duration::from_millis(20)).await;
+            }
+            assert_eq!(watch.version(), 3);
+            drop(watch);
+            for handle in handles {
+                let seen = handle.await;
+                assert_eq!(seen.last(), Some(&3));
+                assert!(seen.windows(2).all(|w| w[0] < w[1]));
+            }
+        });
+    }
+
+    #[test]
+    fn test_borrow_and_update() {
+        let watch = NotifyWatch::new("a");
+        let mut receiver = watch.subscribe();
+        assert!(!receiver.has_changed());
+        assert_eq!(watch.set("b"), "a");
+        watch.set("c");
+        assert!(receiver.has_changed());
+        {
+            let value = receiver.borrow_and_update();
+            assert_eq!(*value, "c");
+            assert_eq!(value.version(), 2);
+        }
+        assert!(!receiver.has_changed());
+        assert_eq!(receiver.borrow_with(|value| value.len()), 1);
+        assert_eq!(watch.borrow_with(|value| value.to_string()), "c");
+    }
+
+    #[cfg(feature = "critical-section")]
+    #[test]
+    fn test_snapshot_borrow() {
+        let first = NotifyWatch::new(1u32);
+        let second = NotifyWatch::new(2u32);
+        let a = first.borrow();
+        let b = second.borrow();
+        // neither borrow keeps a critical section open
+        first.set(3);
+        second.set(4);
+        assert_eq!((*a, a.version()), (1, 0));
+        assert_eq!((*b, b.version()), (2, 0));
+        assert_eq!(std::thread::scope(|s| s.spawn(move || *b).join().unwrap()), 2);
+        assert_eq!((*first.borrow(), *second.borrow()), (3, 4));
+    }
+}